@@ -0,0 +1,57 @@
+//! Checks that a statemachine with option `no_std` compiles in a `#![no_std]` crate. Built as a library, as a binary
+//! would need a panic handler and an entry point of the target.
+#![no_std]
+
+use simple_statemachine::statemachine;
+
+pub type Brightness = u8;
+
+statemachine!{
+    [no_std]
+    Name                LampStatemachine
+    InitialState        Off
+    EventPayload        Brightness
+    UnexpectedHandler   unexpected_event
+    EventQueueCapacity  2
+    History             4
+
+    Off {
+        OnEntry switch_off
+        SwitchOn[is_powered] == set_brightness => On
+    }
+    On {
+        OnExit leave_on
+        Dim == set_brightness => On
+        SwitchOff => Off
+    }
+}
+
+pub struct Lamp {
+    pub powered: bool,
+    pub brightness: Brightness,
+}
+
+impl LampStatemachineHandler for Lamp {
+    fn unexpected_event(&mut self, _ctx: &mut LampStatemachineContext, _state: LampStatemachineState,
+                        _event: LampStatemachineEventKind) {}
+    fn switch_off(&mut self, _ctx: &mut LampStatemachineContext) {
+        self.brightness = 0;
+    }
+    fn leave_on(&mut self, _ctx: &mut LampStatemachineContext) {}
+    fn is_powered(&self) -> bool {
+        self.powered
+    }
+    fn set_brightness(&mut self, ctx: &mut LampStatemachineContext, brightness: &Brightness) {
+        self.brightness = *brightness;
+        if *brightness == 0 {
+            ctx.post(LampStatemachineEvent::SwitchOff(0));
+        }
+    }
+}
+
+pub fn run() -> Brightness {
+    let mut sm = LampStatemachine::new(Lamp { powered: true, brightness: 0 });
+    sm.event(LampStatemachineEvent::SwitchOn(10));
+    sm.event(LampStatemachineEvent::Dim(5));
+    sm.into_handler().brightness
+}
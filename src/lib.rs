@@ -6,17 +6,36 @@
 //!     * [Using action handlers](#using-action-handlers)
 //!     * [Using entry/exit handlers](#using-entryexit-handlers)
 //!     * [Using guards](#using-guards)
+//! * [Starting and Stopping](#starting-and-stopping)
 //! * [Events With Payload](#events-with-payload)
 //! * [Handling Unexpected Events](#handling-unexpected-events)
 //! * [Sending Events from Handlers](#sending-events-from-handlers)
 //! * [Accessing the Handler](#accessing-the-handler)
+//! * [Querying Accepted Events](#querying-accepted-events)
+//! * [State and Event Names](#state-and-event-names)
+//! * [Observing Transitions](#observing-transitions)
+//! * [Logging and Tracing](#logging-and-tracing)
+//! * [Transition History](#transition-history)
+//! * [Snapshots](#snapshots)
+//! * [Serialization (serde)](#serialization-serde)
+//! * [Analysis of the Definition](#analysis-of-the-definition)
+//! * [Mock Handler](#mock-handler)
+//! * [Scenarios](#scenarios)
+//! * [Random Walks](#random-walks)
+//! * [Transition Coverage](#transition-coverage)
 //! * [Extended Options](#extended-options)
+//! * [Thread-Safe Statemachines](#thread-safe-statemachines)
+//! * [Embedded Targets (no_std)](#embedded-targets-no_std)
+//! * [Direct Ownership](#direct-ownership)
+//! * [Async Handlers](#async-handlers)
+//! * [Running as an Actor](#running-as-an-actor)
 //! * **[Interface Reference](#interface-reference)**
 //!     * [Created types and traits](#created-types-and-traits)
 //!     * [Trait functions - entry, exit, and action handlers, and guards](#trait-functions---entry-exit-and-action-handlers-and-guards)
 //!        * [Action handler](#action-handler)
 //!        * [Entry and exit handlers](#entry-and-exit-handlers)
 //!        * [Guards](#guards)
+//!        * [Observers](#observers)
 //!     * [Statemachine interface functions](#statemachine-interface-functions)
 //! # Simple examples
 //!
@@ -242,6 +261,71 @@
 //!
 //! [(back to top)](index.html)
 //!
+//! # Starting and Stopping
+//!
+//! A new statemachine is in its initial state, but not running yet. `start()` runs the entry handler of the initial
+//! state, and processes any events sent by it. So the traffic light of the
+//! [entry/exit handlers](#using-entryexit-handlers) example switches on "Don't Walk" on start. If `start()` is not
+//! called, the first call to `event()` starts the statemachine before processing the event.
+//!
+//! `stop()` runs the exit handler of the current state, and drops all queued events, including the events sent by
+//! the exit handler. A stopped statemachine doesn't process events, `event()` returns `Stopped`. `start()` restarts
+//! it in its current state. `is_running()` tells whether the statemachine is started and not stopped.
+//!
+//! `reset()` brings the statemachine back to its initial state, without creating a new one, so references to the
+//! handler obtained by `get_handler()` stay valid. It runs the exit handler of the current state, drops all queued
+//! events, and enters the initial state, running its entry handler. Afterwards, the statemachine is running.
+//!
+//! Called from a handler, `start()`, `stop()`, and `reset()` take effect after the event being processed, before any
+//! queued event.
+//!
+//!```
+//! # use simple_statemachine::statemachine;
+//! statemachine!{
+//!    Name Lamp
+//!    InitialState Off
+//!
+//!    Off {
+//!        OnEntry switch_off
+//!        Toggle => On
+//!    }
+//!    On {
+//!        OnEntry switch_on
+//!        OnExit leave_on
+//!        Toggle => Off
+//!    }
+//! }
+//!
+//! struct Switch{ on: bool }
+//! impl LampHandler for Switch{
+//!     fn switch_on(&mut self) { self.on=true; }
+//!     fn switch_off(&mut self) { self.on=false; }
+//!     fn leave_on(&mut self) { self.on=false; }
+//! }
+//!
+//! let sm=Lamp::new(Switch{ on: true });
+//! sm.start();
+//! assert!(!sm.get_handler_ref().on);
+//! sm.event(LampEvent::Toggle);
+//! assert!(sm.get_handler_ref().on);
+//! sm.stop();
+//! assert!(!sm.get_handler_ref().on);
+//! assert_eq!(sm.event(LampEvent::Toggle), LampOutcome::Stopped);
+//! sm.reset();
+//! assert_eq!(sm.get_state(), LampState::Off);
+//! assert!(sm.is_running());
+//!```
+//!
+//! Entry and exit handlers taking transition info (see [Extended Options](#extended-options)) can't be called by
+//! `start()`, `stop()`, and `reset()`, since there is no event. These functions change the state and the lifecycle
+//! of such statemachines without calling these handlers.
+//!
+//! The option `legacy_start` keeps the behavior of former versions: without `start()`, the first call to `event()`
+//! makes the statemachine run without calling the entry handler of the initial state, and `reset()` doesn't call it
+//! either.
+//!
+//! [(back to top)](index.html)
+//!
 //! # Events With Payload
 //!
 //! Sometimes, you would like to include payload with events, e.g. a received data block in a `Received` event. The
@@ -289,7 +373,8 @@
 //!
 //! The world is not perfect, and sometimes an event might be sent to the statemachine that is not expected. The
 //! default behavior is to ignore the unexpected event. If you would like the statemachine to react to unexpected
-//! events, a special handler can be defined by the optional parameter `UnexpectedHandler`:
+//! events, a special handler can be defined by the optional parameter `UnexpectedHandler`. It receives the current
+//! state and the kind of the unexpected event:
 //!
 //!```
 //! # use std::str::from_utf8;
@@ -313,7 +398,7 @@
 //!
 //! struct Receiver{}
 //! impl ReceiverStatemachineHandler for Receiver {
-//!     fn unexpected_event_handler(&mut self, state: ReceiverStatemachineState, event: &ReceiverStatemachineEvent) {
+//!     fn unexpected_event_handler(&mut self, state: ReceiverStatemachineState, event: ReceiverStatemachineEventKind) {
 //!        println!("Received an unexpected event in state {:?}: {:?}",state,event);
 //!     }
 //!     //...//
@@ -337,8 +422,52 @@
 //! # Sending Events from Handlers
 //!
 //! For some statemachine constructs, it might be necessary to send events from inside the handler functions (enter,
-//! exit, action). For this special use case, the statemachine defines a function `event_from_handler()`. Use of
-//! `event()` from inside a handler function is undefined behavior.
+//! exit, action). For this special use case, the statemachine defines a function `event_from_handler()`. Calling
+//! `event()` from inside a handler function has the same effect: the statemachine detects the nested call and queues
+//! the event instead of processing it right away (run-to-completion). If the event queue is full, `event()` returns
+//! the rejected event in the outcome `QueueFull`.
+//!
+//! Events sent from handlers are stored in a queue, and processed in the order they were sent, after the current
+//! event is completely processed. By default, the queue grows as needed. The optional parameter
+//! `EventQueueCapacity` limits the number of queued events. When the queue is full, the new event is rejected, the
+//! queued events are never dropped: `event_from_handler()` panics, while `try_event_from_handler()` returns the
+//! rejected event in a `<Name>QueueFullError`, leaving it to the caller to drop or otherwise handle it:
+//!
+//! ```
+//! # use simple_statemachine::statemachine;
+//! statemachine!{
+//!     Name               BoundedMachine
+//!     InitialState       Init
+//!     EventQueueCapacity 4
+//!
+//!     Init{
+//!         Start => Running
+//!     }
+//!     Running{}
+//! }
+//! ```
+//!
+//! Queued events are processed iteratively, so long chains of events sent from handlers don't grow the stack. A
+//! handler sending an event on every step, however, makes `event()` loop forever. The optional parameter `MaxSteps`
+//! limits the number of events processed by a single call to `event()`, including the event passed to it. If the
+//! limit is exceeded, `event()` stops processing, drops the remaining queued events, and returns the outcome
+//! `StepLimitExceeded`, reporting a likely infinite event loop. `start()` and `reset()` stop processing the events
+//! posted by the entry handler in the same way, counting the entry as the first step:
+//!
+//! ```
+//! # use simple_statemachine::statemachine;
+//! statemachine!{
+//!     Name               BoundedMachine
+//!     InitialState       Init
+//!     EventQueueCapacity 4
+//!     MaxSteps           100
+//!
+//!     Init{
+//!         Start => Running
+//!     }
+//!     Running{}
+//! }
+//! ```
 //!
 //! To be able to access the statemachine from the handler struct, the handler must hold a reference to the
 //! statemachine. This leads to a somewhat more complicated handling.
@@ -445,568 +574,3612 @@
 //!
 //!  [(back to top)](index.html)
 //!
-//! # Extended Options
-//!
-//! For special needs, the signatures of the handler (entry, exit, action) and guard functions can be changed to take
-//! extended transition (state and event) information. These extended signatures are activated by an option list in
-//! square brackets after the opening brace of the statemachine definition.
+//! # Querying Accepted Events
 //!
-//! The following options are available:
-//! * entry_handler_with_transition_info - adds transition info to entry handlers
-//! * exit_handler_with_transition_info - adds transition info to exit_handlers
-//! * action_handler_with_transition_info - adds transition info to action handlers
-//! * guard_with_transition_info - adds transition info to guard functions
+//! User interfaces often need to know which events the statemachine will accept in its current state, e.g. to
+//! enable or disable buttons. `accepted_events()` returns the [kinds](#created-types-and-traits) of all events that
+//! have a transition in the current state. Guards are not evaluated for this list. `can_handle()` checks a single
+//! event, evaluating guards the same way `event()` would, but without calling any other handler function or changing
+//! the state.
 //!
-//!```
+//! ```
 //! # use simple_statemachine::statemachine;
 //! statemachine!{
-//!     [
-//!         entry_handler_with_transition_info,
-//!         exit_handler_with_transition_info,
-//!         action_handler_with_transition_info,
-//!         guard_with_transition_info
-//!     ]
-//!     Name OptionMachine
-//!     InitialState Init
+//!     Name DoorMachine
+//!     InitialState Closed
 //!
-//!     Init{
-//!         OnExit init_on_exit
-//!         OneEvent[guard] ==action=> SecondState
+//!     Closed {
+//!         Open[is_unlocked] => Opened
+//!         Lock => Locked
 //!     }
-//!     SecondState{
-//!         OnEntry second_on_entry
+//!     Locked {
+//!         Unlock => Closed
+//!     }
+//!     Opened {
+//!         Close => Closed
 //!     }
 //! }
-//! struct Handler{}
-//! impl OptionMachineHandler for Handler{
-//!     fn second_on_entry(
-//!            &mut self,
-//!            old_state: OptionMachineState,
-//!            event: &OptionMachineEvent,
-//!            new_state: OptionMachineState
-//!     ) { /*...*/ }
 //!
-//!     fn init_on_exit(
-//!            &mut self,
-//!            old_state: OptionMachineState,
-//!            event: &OptionMachineEvent,
-//!            new_state: OptionMachineState
-//!     ) { /*...*/ }
+//! struct Door{
+//!     unlocked: bool
+//! }
+//! impl DoorMachineHandler for Door{
+//!     fn is_unlocked(&self) -> bool {
+//!         self.unlocked
+//!     }
+//! }
 //!
-//!     fn guard(
-//!            &self,
-//!            state: OptionMachineState,
-//!            event: &OptionMachineEvent
-//!     ) -> bool { /*...*/ false }
+//! let sm=DoorMachine::new(Door{unlocked:false});
+//! assert_eq!(sm.accepted_events(),[DoorMachineEventKind::Open,DoorMachineEventKind::Lock]);
+//! assert!(!sm.can_handle(&DoorMachineEvent::Open));
+//! assert!(sm.can_handle(&DoorMachineEvent::Lock));
+//! assert!(!sm.can_handle(&DoorMachineEvent::Close));
 //!
-//!     fn action(
-//!            &mut self,
-//!            old_state: OptionMachineState,
-//!            event: &OptionMachineEvent,
-//!            new_state: OptionMachineState
-//!     ) { /*...*/ }
-//! }
+//! sm.get_handler_mut().unlocked=true;
+//! assert!(sm.can_handle(&DoorMachineEvent::Open));
+//! ```
+//!
+//! The topology of the statemachine can be examined without a statemachine or a handler at all. The state type
+//! provides the table `TRANSITIONS` of all transitions, each as tuple of state, event kind, guard name, and target
+//! state. `next_states()` returns the possible targets of an event in a state, each with its guard name:
+//!
+//! ```
+//! # use simple_statemachine::statemachine;
+//! # statemachine!{
+//! #     Name DoorMachine
+//! #     InitialState Closed
+//! #
+//! #     Closed {
+//! #         Open[is_unlocked] => Opened
+//! #         Lock => Locked
+//! #     }
+//! #     Locked {
+//! #         Unlock => Closed
+//! #     }
+//! #     Opened {
+//! #         Close => Closed
+//! #     }
+//! # }
+//! assert_eq!(DoorMachineState::Closed.next_states(DoorMachineEventKind::Open),
+//!            [(Some("is_unlocked"), DoorMachineState::Opened)]);
+//! assert!(DoorMachineState::Locked.next_states(DoorMachineEventKind::Open).is_empty());
+//! assert_eq!(DoorMachineState::TRANSITIONS.len(), 4);
 //! ```
 //!
 //!  [(back to top)](index.html)
 //!
-//! # Interface Reference
+//! # State and Event Names
 //!
-//! ## Statemachine DSL
+//! States and event kinds know their names as given in the statemachine definition. `as_str()` returns the name,
+//! `Display` writes it, and `FromStr` parses it back. This is handy for logging, for persisting states, or for
+//! parsing events given on a command line. Unknown names are rejected with a `<Name>ParseError`.
 //!
-//! This is an example of a statemachine using all available features:
 //! ```
 //! # use simple_statemachine::statemachine;
-//! # type OptionalEventPayloadType=Option<bool>;
 //! statemachine!{
-//!     [
-//!         action_handler_with_transition_info,
-//!         entry_handler_with_transition_info,
-//!         exit_handler_with_transition_info,
-//!         guard_with_transition_info
-//!     ]
-//!     Name                StatemachineName
-//!     InitialState        StateName1
-//!     EventPayload        OptionalEventPayloadType
-//!     UnexpectedHandler   ueh_function_name_optional
+//!     Name TrafficLightStatemachine
+//!     InitialState DontWalk
 //!
-//!     StateName1 {
-//!         OnEntry on_entry_function_name1_optional
-//!         OnExit  on_exit_function_name1_optional
-//!         EventName1[guard_function_name_optional] == action_function_name1_optional => StateName2
-//!         EventName2 == action_function_name2_optional => StateName3
-//!         EventName3[guard_function_name_optional] == action_function_name_optional => StateName3
-//!         EventName4[guard_function_name_optional] == action_function_name_optional => StateName3
+//!     DontWalk {
+//!         TimerFired => Walk
 //!     }
+//!     Walk {
+//!         TimerFired => DontWalk
+//!     }
+//! }
+//! struct LightSwitch{}
+//! impl TrafficLightStatemachineHandler for LightSwitch{}
 //!
-//!     StateName2 {
-//!         OnEntry on_entry_function_name2_optional
-//!         OnExit  on_exit_function_name2_optional
+//! let sm=TrafficLightStatemachine::new(LightSwitch{});
+//! assert_eq!(sm.get_state().as_str(),"DontWalk");
 //!
-//!         EventName2[guard_function_name_optional] => StateName3
-//!         EventName3[guard_function_name_optional] == action_function_name_optional => StateName3
-//!         EventName4[guard_function_name_optional] == action_function_name_optional => StateName3
-//!     }
+//! let kind: TrafficLightStatemachineEventKind="TimerFired".parse().unwrap();
+//! assert_eq!(kind,TrafficLightStatemachineEventKind::TimerFired);
+//! assert_eq!(format!("{}",kind),"TimerFired");
 //!
-//!     StateName3{}
-//! }
+//! let state="Walk".parse::<TrafficLightStatemachineState>();
+//! assert_eq!(state,Ok(TrafficLightStatemachineState::Walk));
+//! assert_eq!("Run".parse::<TrafficLightStatemachineState>(),Err(TrafficLightStatemachineParseError));
 //! ```
 //!
 //!  [(back to top)](index.html)
 //!
-//! ## Created types and traits
+//! # Observing Transitions
 //!
-//! The `Name` of the statemachine is used as a base name for
-//! * Event type,
-//! * State type, and
-//! * Trait name
+//! Every handler trait has two observer functions with an empty default implementation. `on_transition()` is called
+//! after every transition, after the entry handler of the new state. `on_event_ignored()` is called for every event
+//! that doesn't trigger a transition, i.e. events blocked by guards, unexpected, or ignored events. Override them
+//! to log or audit all transitions in a single place, independent of the extended options.
 //!
-//! A name `MyMachine` creates
 //! ```
-//! enum MyMachineEvent{
-//! //...
+//! # use simple_statemachine::statemachine;
+//! statemachine!{
+//!     Name TrafficLightStatemachine
+//!     InitialState DontWalk
+//!
+//!     DontWalk {
+//!         TimerFired => Walk
+//!     }
+//!     Walk {
+//!         TimerFired => DontWalk
+//!     }
 //! }
-//! enum MyMachineState{
-//! //...
+//! struct AuditLog{
+//!     entries: Vec<String>
 //! }
-//! trait MyMachineHandler{
-//! //...
+//! impl TrafficLightStatemachineHandler for AuditLog{
+//!     fn on_transition(&mut self,
+//!                      old_state: TrafficLightStatemachineState,
+//!                      event: &TrafficLightStatemachineEvent,
+//!                      new_state: TrafficLightStatemachineState) {
+//!         self.entries.push(format!("{} --{}--> {}",old_state,event.kind(),new_state));
+//!     }
 //! }
-//! ```
-//! The enum values are exactly as given in the statemachine definition.
 //!
-//! If `EventPayload` is defined, all event values are created with this payload type. An event payload of
-//! `MyPayload` gives e.g.
-//! ```
-//! # type MyPayload=bool;
-//! enum MyMachineEvent {
-//!     FirstEvent(MyPayload),
-//!     SecondEvent(MyPayload),
-//! }
+//! let sm=TrafficLightStatemachine::new(AuditLog{entries:Vec::new()});
+//! sm.event(TrafficLightStatemachineEvent::TimerFired);
+//! assert_eq!(sm.get_handler_ref().entries,["DontWalk --TimerFired--> Walk"]);
 //! ```
 //!
-//!  [(back to top)](index.html)
+//! Observers are never `async`, and don't receive a context, even with options `async`, `no_std`, or
+//! `direct_ownership`. Handler functions can't be named `on_transition` or `on_event_ignored`, the definition is
+//! rejected.
 //!
-//! ## Trait functions - entry, exit, and action handlers, and guards
+//!  [(back to top)](index.html)
 //!
-//! The function names of handlers and guard functions are exactly as given in the statemachine definition. The
-//! signature depends on the options given, see also [Extended Options](#extended-options).
+//! # Logging and Tracing
 //!
-//! ### Action handler
-//! Action handlers are called while transitioning from one state to the next, after the old state's exit handler and
-//! before the new state's entry handler.
+//! With the option `log` or `tracing`, the generated statemachine emits log records via the
+//! [`log`](https://docs.rs/log) crate or events via the [`tracing`](https://docs.rs/tracing) crate. The crate in
+//! question must be a dependency of the crate using the statemachine:
 //!
-//! #### Without payload
-//! ```
-//! trait MyMachineHandler {
-//!     fn action_handler(&mut self);
-//! }
-//! ```
-//! #### With payload
-//! ```
-//! # type MyPayload=bool;
-//! trait MyMachineHandler {
-//!     fn action_handler(&mut self,
-//!            payload: &MyPayload);
-//! }
-//! ```
-//! #### Without payload, with transition info option
 //! ```
-//! # type MyMachineState=bool;
-//! # type MyMachineEvent=bool;
-//! trait MyMachineHandler {
-//!     fn action_handler(&mut self,
-//!            old_state: MyMachineState,
-//!            event: &MyMachineEvent,
-//!            new_state: MyMachineState);
+//! # use simple_statemachine::statemachine;
+//! statemachine!{
+//!     [log]
+//!     Name TrafficLightStatemachine
+//!     InitialState DontWalk
+//!
+//!     DontWalk {
+//!         TimerFired => Walk
+//!     }
+//!     Walk {
+//!         TimerFired => DontWalk
+//!     }
 //! }
 //! ```
-//! #### With payload, with transition info option
+//!
+//! Each record names the statemachine, and carries its details as fields (tracing) or `name=value` pairs (log):
+//!
+//! | Record            | Level   | Fields                             |
+//! |-------------------|---------|------------------------------------|
+//! | dispatching event | `debug` | `state`, `event` (the event kind)  |
+//! | guard evaluated   | `trace` | `state`, `guard`, `accepted`       |
+//! | calling action    | `trace` | `action`                           |
+//! | state changed     | `debug` | `old_state`, `new_state`           |
+//!
+//! E.g., the log record for a state change reads `TrafficLightStatemachine: state changed, old_state=DontWalk,
+//! new_state=Walk`. With tracing, the statemachine's name is given in field `statemachine`, and the processing of
+//! each event runs in a `debug` span named `event`, carrying the fields `statemachine`, `state`, and `event`.
+//!
+//!  [(back to top)](index.html)
+//!
+//! # Transition History
+//!
+//! The optional parameter `History` makes the statemachine record its most recent transitions in a ring buffer of
+//! the given size. `history()` returns the `<Name>History`, iterating its `<Name>HistoryEntry`s from oldest to most
+//! recent. Each entry holds a sequence number counting all transitions, the time of the transition, the old state,
+//! the kind of the triggering event, the name of the guard that accepted the transition (if any), and the new state.
+//! With option `no_std`, entries have no timestamp.
+//!
 //! ```
-//! # type MyPayload=bool;
-//! # type MyMachineState=bool;
-//! # type MyMachineEvent=bool;
-//! trait MyMachineHandler {
-//!     fn action_handler(&mut self,
-//!            old_state: MyMachineState,
-//!            event: &MyMachineEvent,
-//!            new_state: MyMachineState,
-//!            payload: &MyPayload);
+//! # use simple_statemachine::statemachine;
+//! statemachine!{
+//!     Name TrafficLightStatemachine
+//!     InitialState DontWalk
+//!     History 8
+//!
+//!     DontWalk {
+//!         TimerFired => Walk
+//!     }
+//!     Walk {
+//!         TimerFired => DontWalk
+//!     }
 //! }
+//! struct LightSwitch{}
+//! impl TrafficLightStatemachineHandler for LightSwitch{}
+//!
+//! let sm=TrafficLightStatemachine::new(LightSwitch{});
+//! sm.event(TrafficLightStatemachineEvent::TimerFired);
+//! sm.event(TrafficLightStatemachineEvent::TimerFired);
+//!
+//! let history=sm.history();
+//! assert_eq!(history.len(),2);
+//! let last=history.last().unwrap();
+//! assert_eq!(last.seq,1);
+//! assert_eq!(last.old_state,TrafficLightStatemachineState::Walk);
+//! assert_eq!(last.event,TrafficLightStatemachineEventKind::TimerFired);
+//! assert_eq!(last.guard,None);
+//! assert_eq!(last.new_state,TrafficLightStatemachineState::DontWalk);
 //! ```
 //!
 //!  [(back to top)](index.html)
 //!
-//! ### Entry and exit handlers
-//! Entry handlers are called directly after a transition has happened. If an action is defined for the transition,
-//! the action is called before the entry handler.
+//! # Snapshots
 //!
-//! Exit handlers are called directly before a transition happens. If an action is defined for the transition, the
-//! action is called after the exit handler. If a guard is defined for the event, the exit handler is called after
-//! the guard.
+//! `snapshot()` returns a `<Name>Snapshot`, a plain data struct holding the current state, the `<Name>Lifecycle`,
+//! i.e. whether the statemachine was started or stopped, the events sent from handlers that are not processed yet,
+//! and the transition history if `History` is given. `restore()` creates a new statemachine from a snapshot and a
+//! handler, resuming where the snapshot was taken, e.g. after a reboot. Unlike `new()`, `restore()` doesn't start in
+//! the `InitialState`, and a statemachine stopped when the snapshot was taken is stopped after restoring, too.
+//! Neither function calls any handler function.
+//!
+//! With option `no_std`, the pending events and the history are arrays of the respective capacity, filled from the
+//! start, otherwise they are `Vec`s.
 //!
-//! #### Standard
 //! ```
-//! trait MyMachineHandler {
-//!     fn entry_exit_handler(&mut self);
-//! }
-//! ```
-//! #### With transition info option
-//! ```
-//! # type MyMachineState=bool;
-//! # type MyMachineEvent=bool;
-//! trait MyMachineHandler {
-//!     fn entry_exit_handler(&mut self,
-//!            old_state: MyMachineState,
-//!            event: &MyMachineEvent,
-//!            new_state: MyMachineState);
+//! # use simple_statemachine::statemachine;
+//! statemachine!{
+//!     Name TrafficLightStatemachine
+//!     InitialState DontWalk
+//!
+//!     DontWalk {
+//!         TimerFired => Walk
+//!     }
+//!     Walk {
+//!         TimerFired => DontWalk
+//!     }
 //! }
+//! struct LightSwitch{}
+//! impl TrafficLightStatemachineHandler for LightSwitch{}
+//!
+//! let sm=TrafficLightStatemachine::new(LightSwitch{});
+//! sm.event(TrafficLightStatemachineEvent::TimerFired);
+//! let snapshot=sm.snapshot();
+//! assert_eq!(snapshot.state,TrafficLightStatemachineState::Walk);
+//!
+//! let sm=TrafficLightStatemachine::restore(LightSwitch{},snapshot);
+//! assert_eq!(sm.get_state(),TrafficLightStatemachineState::Walk);
 //! ```
 //!
 //!  [(back to top)](index.html)
 //!
-//! ### Guards
-//! Guards are run before any entry, exit, or action handlers, since they decide which transition will be executed.
-//! For details see [Using guards](#using-guards).
+//! # Serialization (serde)
 //!
-//! Guards return a `bool`. If the return value is `true`, the guarded transition will be executed.
+//! With the option `serde`, the state, event, event kind, and lifecycle enums, the snapshot, and the history entries
+//! implement `Serialize` and `Deserialize` of the [`serde`](https://docs.rs/serde) crate. The crate using the
+//! statemachine must depend on `serde`, and the `EventPayload` type must implement `Serialize` and `Deserialize`,
+//! too:
 //!
-//! #### Standard
 //! ```
-//! trait MyMachineHandler {
-//!     fn my_guard(&mut self) -> bool;
+//! # use simple_statemachine::statemachine;
+//! #[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+//! struct Passenger {
+//!     name: String,
 //! }
-//! ```
-//! #### With transition info option
-//! ```
-//! # type MyMachineState=bool;
-//! # type MyMachineEvent=bool;
-//! trait MyMachineHandler {
-//!     fn my_guard(&mut self,
-//!            state: MyMachineState,
-//!            event: &MyMachineEvent
-//!        ) -> bool;
+//!
+//! statemachine!{
+//!     [serde]
+//!     Name Elevator
+//!     InitialState Idle
+//!     EventPayload Passenger
+//!
+//!     Idle {
+//!         Board => Moving
+//!     }
+//!     Moving {
+//!         Arrive => Idle
+//!     }
 //! }
 //! ```
 //!
+//! Guard names in history entries are deserialized to the names of the guards defined in the statemachine, unknown
+//! names are rejected. With option `no_std`, guard names are borrowed from the input, so the deserializer must
+//! support borrowing strings. Since serde implements its traits for arrays of up to 32 elements, only, larger
+//! `EventQueueCapacity` and `History` sizes are rejected with option `no_std`.
+//!
 //!  [(back to top)](index.html)
 //!
-//! ## Statemachine interface
-//! The created statemachine struct defines several functions to interact with the statemachine.
+//! # Analysis of the Definition
 //!
-//! ### Statemachine definition
-//! The statemachine handler is given as a generic parameter (e.g. statemachine `Name MyStatemachine`):
+//! The `statemachine!()` macro analyzes the graph of states and transitions, and warns about
+//! * states that are not reachable from the initial state,
+//! * states from which no final state (a state without transitions) is reachable, if the statemachine has final
+//!   states at all, and
+//! * events that are handled in unreachable states only, so they can never be handled.
+//!
+//! Guards are assumed to accept their events eventually. Since procedural macros can't emit warnings on stable Rust,
+//! the findings are reported as the use of the deprecated item `StatemachineAnalysisWarning`, pointing at the
+//! offending state or event:
+//!
+//! ```text
+//! warning: use of deprecated unit struct `_::StatemachineAnalysisWarning`: State Broken is not reachable from
+//!          initial state Locked
 //! ```
-//! struct MyStatemachine<Handler>{
-//!     /*...*/
-//! # h:Handler
+//!
+//! `#[allow(deprecated)]` silences the warnings, the option `strict` turns them into errors:
+//!
+//!```compile_fail
+//! # use simple_statemachine::statemachine;
+//! statemachine!{
+//!     [strict]
+//!     Name Turnstile
+//!     InitialState Locked
+//!
+//!     Locked {
+//!         Coin => Unlocked
+//!     }
+//!     Unlocked {
+//!         Push => Locked
+//!     }
+//!     Broken {
+//!         Repair => Locked
+//!     }
 //! }
-//! impl<Handler> MyStatemachine<Handler>{
-//!      /*...*/
+//!```
+//!
+//! [(back to top)](index.html)
+//!
+//! # Mock Handler
+//!
+//! For tests, the option `mock_handler` creates a handler implementing the handler trait, named after the
+//! statemachine, e.g. `DoorMockHandler`. It records every call of an entry, exit, action, or unexpected event handler
+//! in its public field `calls`, as a value of e.g. `DoorMockCall`. This enum has a variant per handler function, its
+//! name converted to CamelCase. The variant holds the arguments the handler was called with, if any, i.e. the payload
+//! of action handlers, and the transition info with [Extended Options](#extended-options).
+//!
+//! All guards return `true`, until changed by their setter, named after the guard, e.g. `set_unlocked()` for the
+//! guard `unlocked`.
+//!
+//!```
+//! # use simple_statemachine::statemachine;
+//! statemachine!{
+//!     [mock_handler]
+//!     Name Door
+//!     InitialState Closed
+//!     EventPayload u32
+//!
+//!     Closed {
+//!         Open[unlocked] ==log_open=> Opened
+//!     }
+//!     Opened {
+//!         OnEntry start_timer
+//!         Close => Closed
+//!     }
 //! }
-//! ```
 //!
-//! ### Functions
-//! ##### new()
-//! ```
-//! # struct Statemachine<Handler>{ h:Handler }
-//! # impl<Handler> Statemachine<Handler> {
-//! pub fn new(handler: Handler) -> Self
-//! #  {Self{h:handler}}
-//! # }
-//! ```
-//! Creates a new statemachine instance.
-//! * `handler` - The handler providing handlers and guards
+//! let mut handler=DoorMockHandler::new();
+//! handler.set_unlocked(false);
+//! let sm=Door::new(handler);
+//! sm.event(DoorEvent::Open(1));
+//! sm.get_handler_mut().set_unlocked(true);
+//! sm.event(DoorEvent::Open(2));
+//! assert_eq!(sm.get_handler_ref().calls, [
+//!     DoorMockCall::LogOpen{ payload: 2 },
+//!     DoorMockCall::StartTimer,
+//! ]);
+//!```
 //!
-//! ---
-//! ##### get_handler()
-//! ```
-//! # use std::rc::Rc;
-//! # use std::cell::RefCell;
-//! # struct Statemachine<Handler>{ h:Rc<RefCell<Handler>> }
-//! # impl<Handler> Statemachine<Handler> {
-//! pub fn get_handler(&self) -> Rc<RefCell<Handler>>
-//! #  {self.h.clone()}
-//! # }
-//! ```
-//! Returns a `Rc` reference counted `RefCell` to the owned `Handler`.
+//! With option `mock_handler`, the mock handler is compiled in all builds, not only with `cfg(test)`, so the
+//! integration tests in `tests/` and other crates can use the mock handler of a statemachine defined in a library.
+//! The option can't be combined with option `no_std`.
+//! Two handler functions whose names only differ in underscores, e.g. `enter_idle` and `enter__idle`, map to the
+//! same variant, so the definition is rejected.
 //!
-//! ---
-//! #### get_handler_ref()
-//! ```
-//! # use std::rc::Rc;
-//! # use std::cell::{Ref,RefCell};
-//! # struct Statemachine<Handler>{ h:Rc<RefCell<Handler>> }
-//! # impl<Handler> Statemachine<Handler> {
-//! pub fn get_handler_ref(&self) -> Ref<Handler>
-//! #  {self.h.borrow()}
-//! # }
-//! ```
-//! Returns a non-mutably borrowed reference to the owned Handler.
+//! [(back to top)](index.html)
 //!
-//! ---
-//! #### get_handler_mut()
-//! ```
-//! # use std::rc::Rc;
-//! # use std::cell::{RefMut,RefCell};
-//! # struct Statemachine<Handler>{ h:Rc<RefCell<Handler>> }
-//! # impl<Handler> Statemachine<Handler> {
-//! pub fn get_handler_mut(&self) -> RefMut<Handler>
-//! #  {self.h.borrow_mut()}
-//! # }
-//! ```
-//! Returns a mutably borrowed reference to the owned Handler.
+//! # Scenarios
 //!
-//! ---
-//! #### get_state()
-//! ```
-//! # enum StatemachineState{State}
-//! # struct Statemachine<Handler>{h:Handler}
-//! # impl<Handler> Statemachine<Handler> {
-//! pub fn get_state(&self) -> StatemachineState
-//! #  {StatemachineState::State}
-//! # }
-//! ```
-//! Returns the current state of the statemachine.
+//! A scenario describes a test of the statemachine next to its definition: a sequence of events, each followed by the
+//! state expected after processing it. Scenarios may be given between and after the states, each expands to a test
+//! function named by the scenario, e.g. `unlock_and_pass`. The test creates the statemachine with the
+//! [mock handler](#mock-handler), so guards accept their events unless a step sets them otherwise, and fails at the
+//! first step ending in another state than expected. The mock handler is created for statemachines with scenarios,
+//! even without option `mock_handler`, but then only with `cfg(test)`.
 //!
-//! ---
-//! #### event()
-//! ```
-//! # enum StatemachineEvent{Event}
-//! # struct Statemachine<Handler>{ h:Handler }
-//! # impl<Handler> Statemachine<Handler> {
-//! pub fn event(&mut self, ev: &StatemachineEvent)
-//! #  {}
-//! # }
-//! ```
-//! Sends an event to the statemachine, triggering state changes and executing actions.
-//! * `ev` - The event to send to the machine.
+//!```
+//! # use simple_statemachine::statemachine;
+//! statemachine!{
+//!     Name Turnstile
+//!     InitialState Locked
 //!
-//! Calling `event()`from inside a handler function is undefined behavior.
+//!     Locked {
+//!         Coin => Unlocked
+//!     }
+//!     Unlocked {
+//!         Push => Locked
+//!     }
 //!
-//! ---
-//! #### event_from_handler()
-//! ```
-//! # enum StatemachineEvent{Event}
-//! # struct Statemachine<Handler>{ h:Handler }
-//! # impl<Handler> Statemachine<Handler> {
-//! pub fn event_from_handler(&mut self, ev: &StatemachineEvent)
-//! #  {}
-//! # }
-//! ```
-//! Sends an event to the statemachine *from inside a handler function*. This event will saved and executed after
-//! the current event is completely processed, and before control is returned to the caller of [`event()`](#event).
-//! Only one event can be sent from handlers while processing a given event. Additional calls to `event_from_handler()`
-//! will overwrite the saved event.
-//! * `ev` - The event to send to the machine.
+//!     Scenario unlock_and_pass {
+//!         Coin -> Unlocked;
+//!         Push -> Locked;
+//!     }
+//!     Scenario push_locked {
+//!         Push -> Locked;
+//!     }
+//! }
+//!```
+//!
+//! With an `EventPayload`, each event takes its payload in parentheses, e.g. `Coin(50) -> Unlocked`. Guard values are
+//! given in brackets after the event, e.g. `Coin(50) [coin_valid=false] -> Locked`, and are set on the mock handler
+//! before the event is sent. They keep their values for the following steps. Scenarios can't be combined with options
+//! `no_std` and `async`.
+//!
+//! [(back to top)](index.html)
+//!
+//! # Random Walks
+//!
+//! To explore the paths through a statemachine without writing the test sequences by hand, the option `random_walk`
+//! creates the function `random_walk()`. It sends a sequence of random events to the statemachine, and checks an
+//! invariant after each event. The events are chosen by e.g. a `PumpRandomWalk`, a pseudo random number generator
+//! created from a seed, so a failing walk can be reproduced. By default, only events the current state has
+//! transitions for are chosen, and the walk ends early in a final state. With `respect_transitions(false)`, any
+//! event is chosen, exercising the handling of unexpected events, too.
+//!
+//! If the invariant returns an error, `random_walk()` returns a `PumpRandomWalkError`, holding the seed, the message
+//! of the invariant, and the events sent so far.
+//!
+//!```
+//! # use simple_statemachine::statemachine;
+//! statemachine!{
+//!     [random_walk]
+//!     Name Pump
+//!     InitialState Off
+//!
+//!     Off {
+//!         SwitchOn => On
+//!     }
+//!     On {
+//!         OnEntry start_motor
+//!         OnExit stop_motor
+//!         SwitchOff => Off
+//!     }
+//! }
+//!
+//! struct Motors{ running: u32 }
+//! impl PumpHandler for Motors{
+//!     fn start_motor(&mut self) { self.running+=1; }
+//!     fn stop_motor(&mut self) { self.running-=1; }
+//! }
+//!
+//! let sm=Pump::new(Motors{ running: 0 });
+//! let mut walk=PumpRandomWalk::new(42).respect_transitions(false);
+//! sm.random_walk(&mut walk, 1000, |sm| {
+//!     let running=sm.get_handler_ref().running;
+//!     match (sm.get_state(), running) {
+//!         (PumpState::On, 1) | (PumpState::Off, 0) => Ok(()),
+//!         (state, running) => Err(format!("{} motors running in state {}", running, state)),
+//!     }
+//! }).unwrap();
+//!```
+//!
+//! With an `EventPayload`, `random_walk()` takes an additional function creating the payload of an event, given the
+//! event kind and the random walk, whose `next_u64()` and `next_below()` provide random values. The option can't be
+//! combined with `no_std`.
+//!
+//! [(back to top)](index.html)
 //!
-//! Calling `event_from_handler()` from outside a handler function is undefined behavior.
+//! # Transition Coverage
 //!
+//! With the option `coverage`, the statemachine counts how often each transition of its definition is taken, and how
+//! often its guard rejects an event. `coverage()` returns the counters of the statemachine as e.g. a
+//! `TurnstileCoverage`. Its `transitions()` returns the coverage of all transitions, in order of their definition,
+//! `unexercised()` the transitions not taken yet, or whose guard didn't reject an event yet. `merge()` adds the
+//! counters of another statemachine, e.g. to check the coverage of several tests:
 //!
+//!```
+//! # use simple_statemachine::statemachine;
+//! statemachine!{
+//!     [coverage]
+//!     Name Turnstile
+//!     InitialState Locked
+//!
+//!     Locked {
+//!         Coin[paid_enough] => Unlocked
+//!     }
+//!     Unlocked {
+//!         Push => Locked
+//!     }
+//! }
+//!
+//! struct Gate{ paid: bool }
+//! impl TurnstileHandler for Gate{
+//!     fn paid_enough(&self) -> bool { self.paid }
+//! }
+//!
+//! let mut coverage=TurnstileCoverage::new();
+//! for paid in [false, true] {
+//!     let sm=Turnstile::new(Gate{ paid });
+//!     sm.event(TurnstileEvent::Coin);
+//!     coverage.merge(&sm.coverage());
+//! }
+//! let unexercised: Vec<String>=coverage.unexercised().map(|t| t.to_string()).collect();
+//! assert_eq!(unexercised, ["Unlocked --Push--> Locked: taken 0 times, rejected 0 times"]);
+//!```
+//!
+//! Each transition is displayed like `Locked --Coin[paid_enough]--> Unlocked: taken 1 times, rejected 1 times`.
+//!
+//! [(back to top)](index.html)
+//!
+//! # Extended Options
+//!
+//! For special needs, the signatures of the handler (entry, exit, action) and guard functions can be changed to take
+//! extended transition (state and event) information. These extended signatures are activated by an option list in
+//! square brackets after the opening brace of the statemachine definition.
+//!
+//! The following options are available:
+//! * entry_handler_with_transition_info - adds transition info to entry handlers
+//! * exit_handler_with_transition_info - adds transition info to exit_handlers
+//! * action_handler_with_transition_info - adds transition info to action handlers
+//! * guard_with_transition_info - adds transition info to guard functions
+//! * legacy_start - runs the statemachine on the first event without calling the entry handler of the initial state,
+//!   see [Starting and Stopping](#starting-and-stopping)
+//! * mock_handler - creates a handler recording all calls, see [Mock Handler](#mock-handler)
+//! * random_walk - creates a generator of random event sequences, see [Random Walks](#random-walks)
+//! * strict - turns the warnings of the analysis into errors, see
+//!   [Analysis of the Definition](#analysis-of-the-definition)
+//! * log - emits log records via the `log` crate, see [Logging and Tracing](#logging-and-tracing)
+//! * tracing - emits events and spans via the `tracing` crate, see [Logging and Tracing](#logging-and-tracing)
+//! * serde - implements `Serialize` and `Deserialize` for the data types, see
+//!   [Serialization (serde)](#serialization-serde)
+//! * coverage - counts how often each transition is taken, see [Transition Coverage](#transition-coverage)
+//!
+//!```
+//! # use simple_statemachine::statemachine;
+//! statemachine!{
+//!     [
+//!         entry_handler_with_transition_info,
+//!         exit_handler_with_transition_info,
+//!         action_handler_with_transition_info,
+//!         guard_with_transition_info
+//!     ]
+//!     Name OptionMachine
+//!     InitialState Init
+//!
+//!     Init{
+//!         OnExit init_on_exit
+//!         OneEvent[guard] ==action=> SecondState
+//!     }
+//!     SecondState{
+//!         OnEntry second_on_entry
+//!     }
+//! }
+//! struct Handler{}
+//! impl OptionMachineHandler for Handler{
+//!     fn second_on_entry(
+//!            &mut self,
+//!            old_state: OptionMachineState,
+//!            event: &OptionMachineEvent,
+//!            new_state: OptionMachineState
+//!     ) { /*...*/ }
 //!
+//!     fn init_on_exit(
+//!            &mut self,
+//!            old_state: OptionMachineState,
+//!            event: &OptionMachineEvent,
+//!            new_state: OptionMachineState
+//!     ) { /*...*/ }
 //!
+//!     fn guard(
+//!            &self,
+//!            state: OptionMachineState,
+//!            event: &OptionMachineEvent
+//!     ) -> bool { /*...*/ false }
+//!
+//!     fn action(
+//!            &mut self,
+//!            old_state: OptionMachineState,
+//!            event: &OptionMachineEvent,
+//!            new_state: OptionMachineState
+//!     ) { /*...*/ }
+//! }
+//! ```
+//!
+//!  [(back to top)](index.html)
+//!
+//! # Thread-Safe Statemachines
+//!
+//! By default, the statemachine keeps its handler in an `Rc<RefCell<>>`, and its state in a `RefCell`, so it can
+//! neither be sent to nor shared with other threads. The option `thread_safe` creates a statemachine with the same
+//! interface, using `Arc<Mutex<>>` and `Mutex` instead. The statemachine is `Send` and `Sync` if the handler and the
+//! event payload are `Send`. Concurrent calls to `event()` are serialized, each event is processed completely before
+//! the next one is started.
+//!
+//! `get_handler()` returns an `Arc<Mutex<Handler>>`, and `get_handler_ref()` and `get_handler_mut()` return a
+//! `MutexGuard`. Don't hold such a guard while sending events, the statemachine would block when calling the handler.
+//! As the handler is locked while one of its functions is called, calling `get_handler_ref()` or `get_handler_mut()`
+//! from a handler function panics instead of deadlocking.
+//!
+//!```
+//! # use std::sync::Arc;
+//! # use std::thread;
+//! # use simple_statemachine::statemachine;
+//! statemachine!{
+//!     [thread_safe]
+//!     Name CounterMachine
+//!     InitialState Counting
+//!
+//!     Counting {
+//!         Tick ==count=> Counting
+//!     }
+//! }
+//!
+//! struct Counter{
+//!     ticks: u32
+//! }
+//! impl CounterMachineHandler for Counter{
+//!     fn count(&mut self) {
+//!         self.ticks+=1;
+//!     }
+//! }
+//!
+//! let sm=Arc::new(CounterMachine::new(Counter{ticks:0}));
+//! let threads: Vec<_>=(0..4).map(|_| {
+//!     let sm=sm.clone();
+//!     thread::spawn(move || {
+//!         for _ in 0..100 {
+//!             sm.event(CounterMachineEvent::Tick);
+//!         }
+//!     })
+//! }).collect();
+//! for t in threads {
+//!     t.join().unwrap();
+//! }
+//! assert_eq!(sm.get_handler_ref().ticks,400);
+//! ```
+//!
+//!  [(back to top)](index.html)
+//!
+//! # Embedded Targets (no_std)
+//!
+//! The option `no_std` creates a statemachine that uses `core` only: no `Rc`, no `RefCell`, and no heap allocation.
+//! The statemachine owns its handler and its state directly, see [Direct Ownership](#direct-ownership).
+//!
+//!```
+//! # use simple_statemachine::statemachine;
+//! statemachine!{
+//!     [no_std]
+//!     Name BlinkMachine
+//!     InitialState Off
+//!
+//!     Off {
+//!         Tick ==switch_on=> On
+//!     }
+//!     On {
+//!         Tick ==switch_off=> Off
+//!     }
+//! }
+//!
+//! struct Led{
+//!     lit: bool
+//! }
+//! impl BlinkMachineHandler for Led{
+//!     fn switch_on(&mut self, _ctx: &mut BlinkMachineContext) {
+//!         self.lit=true;
+//!     }
+//!     fn switch_off(&mut self, _ctx: &mut BlinkMachineContext) {
+//!         self.lit=false;
+//!     }
+//! }
+//!
+//! let mut sm=BlinkMachine::new(Led{lit:false});
+//! sm.event(BlinkMachineEvent::Tick);
+//! assert!(sm.get_handler_ref().lit);
+//! sm.event(BlinkMachineEvent::Tick);
+//! assert!(!sm.into_handler().lit);
+//! ```
+//!
+//!  [(back to top)](index.html)
+//!
+//! # Direct Ownership
+//!
+//! The default statemachine keeps its handler in an `Rc<RefCell<>>`, which costs a runtime borrow check for every call
+//! of a handler function, and panics if the handler is borrowed while the statemachine calls it. The option
+//! `direct_ownership` creates a statemachine that owns its handler and its state directly. The option `no_std` implies
+//! the same layout. `event()` takes `&mut self`, and the handler is accessed by plain references:
+//! * `get_handler_ref()` returns `&Handler`,
+//! * `get_handler_mut()` returns `&mut Handler`,
+//! * `into_handler()` consumes the statemachine and returns the handler.
+//!
+//! `get_handler()` and `event_from_handler()` are not available, since the handler can't hold a reference to the
+//! statemachine. Instead, action, entry, exit, and unexpected event handlers receive a context `<Name>Context` as
+//! first argument after `self`. Events posted to the context by `post()` are processed after the current event, just
+//! like events sent by `event_from_handler()`. Guards don't receive a context. Since `event()` takes `&mut self`,
+//! handlers can't call it.
+//!
+//! With option `no_std`, the event queue is stored inline in the context, and has a fixed capacity of 8 events
+//! unless set by `EventQueueCapacity`. `post()` panics if the queue is full, `try_post()` returns the rejected event
+//! instead.
+//!
+//! Direct ownership can't be combined with option `thread_safe`.
+//!
+//! ```
+//! # use simple_statemachine::statemachine;
+//! statemachine!{
+//!     [direct_ownership]
+//!     Name ComplexMachine
+//!     InitialState Init
+//!
+//!     Init{
+//!         DeferDecision ==decide_now=> IntermediateState
+//!     }
+//!     IntermediateState{
+//!         First => FirstState
+//!         Second => SecondState
+//!     }
+//!     FirstState{}
+//!     SecondState{}
+//! }
+//!
+//! struct Handler{
+//!     choose_second:bool,
+//! }
+//! impl ComplexMachineHandler for Handler{
+//!     fn decide_now(&mut self, ctx: &mut ComplexMachineContext){
+//!         if self.choose_second {
+//!             ctx.post(ComplexMachineEvent::Second);
+//!         } else {
+//!             ctx.post(ComplexMachineEvent::First);
+//!         }
+//!     }
+//! }
+//!
+//! let mut sm=ComplexMachine::new(Handler{ choose_second:true });
+//! sm.event(ComplexMachineEvent::DeferDecision);
+//! assert_eq!(sm.get_state(),ComplexMachineState::SecondState);
+//! ```
+//!
+//!  [(back to top)](index.html)
+//!
+//! # Async Handlers
+//!
+//! With the option `async`, all handler functions and guards in the handler trait are `async fn`s, and the
+//! statemachine awaits them. `event()` and `can_handle()` are `async fn`s as well. The statemachine uses the
+//! [Direct Ownership](#direct-ownership) layout, so `event()` takes `&mut self`, and handlers post events via their
+//! context. The crate doesn't depend on any executor; the returned futures can be run by any of them. The option
+//! `async` can't be combined with `thread_safe`, but with `no_std`.
+//!
+//! ```
+//! # use simple_statemachine::statemachine;
+//! # struct NoopWaker;
+//! # impl std::task::Wake for NoopWaker {
+//! #     fn wake(self: std::sync::Arc<Self>) {}
+//! # }
+//! # fn block_on<F: std::future::Future>(future: F) -> F::Output {
+//! #     let mut future = std::pin::pin!(future);
+//! #     let waker = std::task::Waker::from(std::sync::Arc::new(NoopWaker));
+//! #     let mut cx = std::task::Context::from_waker(&waker);
+//! #     loop {
+//! #         if let std::task::Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+//! #             return output;
+//! #         }
+//! #     }
+//! # }
+//! # async fn send_request() -> bool { true }
+//! statemachine!{
+//!     [async]
+//!     Name ClientMachine
+//!     InitialState Idle
+//!
+//!     Idle {
+//!         Connect ==connect=> Connecting
+//!     }
+//!     Connecting {
+//!         Connected => Online
+//!         Failed => Idle
+//!     }
+//!     Online {}
+//! }
+//!
+//! struct Client{}
+//! impl ClientMachineHandler for Client{
+//!     async fn connect(&mut self, ctx: &mut ClientMachineContext) {
+//!         if send_request().await {
+//!             ctx.post(ClientMachineEvent::Connected);
+//!         } else {
+//!             ctx.post(ClientMachineEvent::Failed);
+//!         }
+//!     }
+//! }
+//!
+//! # block_on(async {
+//! let mut sm=ClientMachine::new(Client{});
+//! sm.event(ClientMachineEvent::Connect).await;
+//! assert_eq!(sm.get_state(),ClientMachineState::Online);
+//! # });
+//! ```
+//!
+//!  [(back to top)](index.html)
+//!
+//! # Running as an Actor
+//!
+//! Often, a statemachine is driven by events received from a channel. `run()` takes ownership of the statemachine
+//! and processes all received events, until the statemachine reaches a final state (a state without any transitions),
+//! or until the channel is closed. `run()` returns the handler. For statemachines sharing their handler, `run()`
+//! panics if a reference returned by `get_handler()` is still alive. Whether a state is final can be checked by
+//! `<Name>State::is_final()`.
+//!
+//! ```
+//! # use std::thread;
+//! # use std::sync::mpsc::channel;
+//! # use simple_statemachine::statemachine;
+//! statemachine!{
+//!     [direct_ownership]
+//!     Name SessionMachine
+//!     InitialState LoggedOut
+//!
+//!     LoggedOut {
+//!         Login => LoggedIn
+//!     }
+//!     LoggedIn {
+//!         Request ==serve=> LoggedIn
+//!         Logout => Closed
+//!     }
+//!     Closed {}
+//! }
+//!
+//! struct Session{
+//!     served: u32
+//! }
+//! impl SessionMachineHandler for Session{
+//!     fn serve(&mut self, _ctx: &mut SessionMachineContext) {
+//!         self.served+=1;
+//!     }
+//! }
+//!
+//! let (sender,receiver)=channel();
+//! let actor=thread::spawn(move || {
+//!     SessionMachine::new(Session{served:0}).run(receiver)
+//! });
+//! sender.send(SessionMachineEvent::Login).unwrap();
+//! sender.send(SessionMachineEvent::Request).unwrap();
+//! sender.send(SessionMachineEvent::Request).unwrap();
+//! sender.send(SessionMachineEvent::Logout).unwrap();
+//! assert_eq!(actor.join().unwrap().served,2);
+//! ```
+//!
+//! With option `async`, `run()` is an `async fn`, and takes a stream of events instead of a channel receiver. The
+//! stream implements the trait `<Name>EventStream`, which mirrors `poll_next()` of the `Stream` trait of the
+//! `futures` crate, e.g. for a tokio channel:
+//!
+//! ```ignore
+//! impl SessionMachineEventStream for tokio::sync::mpsc::Receiver<SessionMachineEvent> {
+//!     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<SessionMachineEvent>> {
+//!         self.poll_recv(cx)
+//!     }
+//! }
+//! ```
+//!
+//! With option `no_std` (and without `async`), `run()` is not available.
+//!
+//!  [(back to top)](index.html)
+//!
+//! # Interface Reference
+//!
+//! ## Statemachine DSL
+//!
+//! This is an example of a statemachine using all available features:
+//! ```
+//! # use simple_statemachine::statemachine;
+//! # type OptionalEventPayloadType=Option<bool>;
+//! statemachine!{
+//!     [
+//!         action_handler_with_transition_info,
+//!         entry_handler_with_transition_info,
+//!         exit_handler_with_transition_info,
+//!         guard_with_transition_info
+//!     ]
+//!     Name                StatemachineName
+//!     InitialState        StateName1
+//!     EventPayload        OptionalEventPayloadType
+//!     UnexpectedHandler   ueh_function_name_optional
+//!     EventQueueCapacity  16
+//!     MaxSteps            1000
+//!     History             16
+//!
+//!     StateName1 {
+//!         OnEntry on_entry_function_name1_optional
+//!         OnExit  on_exit_function_name1_optional
+//!         EventName1[guard_function_name_optional] == action_function_name1_optional => StateName2
+//!         EventName2 == action_function_name2_optional => StateName3
+//!         EventName3[guard_function_name_optional] == action_function_name_optional => StateName3
+//!         EventName4[guard_function_name_optional] == action_function_name_optional => StateName3
+//!     }
+//!
+//!     StateName2 {
+//!         OnEntry on_entry_function_name2_optional
+//!         OnExit  on_exit_function_name2_optional
+//!
+//!         EventName2[guard_function_name_optional] => StateName3
+//!         EventName3[guard_function_name_optional] == action_function_name_optional => StateName3
+//!         EventName4[guard_function_name_optional] == action_function_name_optional => StateName3
+//!     }
+//!
+//!     StateName3{}
+//!
+//!     Scenario scenario_test_name_optional {
+//!         EventName2(None) -> StateName3;
+//!     }
+//! }
+//! ```
+//!
+//!  [(back to top)](index.html)
+//!
+//! ## Created types and traits
+//!
+//! The `Name` of the statemachine is used as a base name for
+//! * Event type,
+//! * Event kind type,
+//! * State type,
+//! * Parse error type (see [State and Event Names](#state-and-event-names)),
+//! * Queue full error type (see [Sending Events from Handlers](#sending-events-from-handlers)),
+//! * Outcome type (see [`event()`](#event)),
+//! * History and history entry types, if `History` is given (see [Transition History](#transition-history)),
+//! * Snapshot and lifecycle types (see [Snapshots](#snapshots)),
+//! * Mock handler and mock call types, with option `mock_handler`, or in tests of statemachines with scenarios (see
+//!   [Mock Handler](#mock-handler)),
+//! * Random walk and random walk error types, with option `random_walk` (see [Random Walks](#random-walks)),
+//! * Event stream trait, with option `async` (see [Running as an Actor](#running-as-an-actor)),
+//! * Coverage and transition coverage types, with option `coverage` (see [Transition Coverage](#transition-coverage)),
+//!   and
+//! * Trait name
+//!
+//! A name `MyMachine` creates
+//! ```
+//! enum MyMachineEvent{
+//! //...
+//! }
+//! enum MyMachineEventKind{
+//! //...
+//! }
+//! enum MyMachineState{
+//! //...
+//! }
+//! trait MyMachineHandler{
+//! //...
+//! }
+//! ```
+//! The enum values are exactly as given in the statemachine definition.
+//!
+//! If `EventPayload` is defined, all event values are created with this payload type. An event payload of
+//! `MyPayload` gives e.g.
+//! ```
+//! # type MyPayload=bool;
+//! enum MyMachineEvent {
+//!     FirstEvent(MyPayload),
+//!     SecondEvent(MyPayload),
+//! }
+//! ```
+//!
+//! The event kind enum has the same values as the event enum, but never carries payload. It names an event without
+//! having to construct (or match) a payload. `MyMachineEvent::kind()` returns the kind of an event:
+//! ```
+//! # type MyPayload=bool;
+//! # #[derive(PartialEq,Debug)]
+//! # enum MyMachineEventKind { FirstEvent, SecondEvent }
+//! # enum MyMachineEvent { FirstEvent(MyPayload), SecondEvent(MyPayload) }
+//! # impl MyMachineEvent {
+//! #     fn kind(&self) -> MyMachineEventKind {
+//! #         match *self {
+//! #             MyMachineEvent::FirstEvent(_) => MyMachineEventKind::FirstEvent,
+//! #             MyMachineEvent::SecondEvent(_) => MyMachineEventKind::SecondEvent,
+//! #         }
+//! #     }
+//! # }
+//! let ev=MyMachineEvent::SecondEvent(true);
+//! assert_eq!(ev.kind(),MyMachineEventKind::SecondEvent);
+//! ```
+//!
+//!  [(back to top)](index.html)
+//!
+//! ## Trait functions - entry, exit, and action handlers, and guards
+//!
+//! The function names of handlers and guard functions are exactly as given in the statemachine definition. The
+//! signature depends on the options given, see also [Extended Options](#extended-options). With options
+//! `direct_ownership` and `no_std`, all handlers except guards take an additional first parameter
+//! `ctx: &mut MyMachineContext`, see [Direct Ownership](#direct-ownership). With option `async`, all functions are
+//! `async fn`s, see [Async Handlers](#async-handlers).
+//!
+//! ### Action handler
+//! Action handlers are called while transitioning from one state to the next, after the old state's exit handler and
+//! before the new state's entry handler.
+//!
+//! #### Without payload
+//! ```
+//! trait MyMachineHandler {
+//!     fn action_handler(&mut self);
+//! }
+//! ```
+//! #### With payload
+//! ```
+//! # type MyPayload=bool;
+//! trait MyMachineHandler {
+//!     fn action_handler(&mut self,
+//!            payload: &MyPayload);
+//! }
+//! ```
+//! #### Without payload, with transition info option
+//! ```
+//! # type MyMachineState=bool;
+//! # type MyMachineEvent=bool;
+//! trait MyMachineHandler {
+//!     fn action_handler(&mut self,
+//!            old_state: MyMachineState,
+//!            event: &MyMachineEvent,
+//!            new_state: MyMachineState);
+//! }
+//! ```
+//! #### With payload, with transition info option
+//! ```
+//! # type MyPayload=bool;
+//! # type MyMachineState=bool;
+//! # type MyMachineEvent=bool;
+//! trait MyMachineHandler {
+//!     fn action_handler(&mut self,
+//!            old_state: MyMachineState,
+//!            event: &MyMachineEvent,
+//!            new_state: MyMachineState,
+//!            payload: &MyPayload);
+//! }
+//! ```
+//!
+//!  [(back to top)](index.html)
+//!
+//! ### Entry and exit handlers
+//! Entry handlers are called directly after a transition has happened. If an action is defined for the transition,
+//! the action is called before the entry handler.
+//!
+//! Exit handlers are called directly before a transition happens. If an action is defined for the transition, the
+//! action is called after the exit handler. If a guard is defined for the event, the exit handler is called after
+//! the guard.
+//!
+//! #### Standard
+//! ```
+//! trait MyMachineHandler {
+//!     fn entry_exit_handler(&mut self);
+//! }
+//! ```
+//! #### With transition info option
+//! ```
+//! # type MyMachineState=bool;
+//! # type MyMachineEvent=bool;
+//! trait MyMachineHandler {
+//!     fn entry_exit_handler(&mut self,
+//!            old_state: MyMachineState,
+//!            event: &MyMachineEvent,
+//!            new_state: MyMachineState);
+//! }
+//! ```
+//!
+//!  [(back to top)](index.html)
+//!
+//! ### Guards
+//! Guards are run before any entry, exit, or action handlers, since they decide which transition will be executed.
+//! For details see [Using guards](#using-guards).
+//!
+//! Guards return a `bool`. If the return value is `true`, the guarded transition will be executed.
+//!
+//! #### Standard
+//! ```
+//! trait MyMachineHandler {
+//!     fn my_guard(&mut self) -> bool;
+//! }
+//! ```
+//! #### With transition info option
+//! ```
+//! # type MyMachineState=bool;
+//! # type MyMachineEvent=bool;
+//! trait MyMachineHandler {
+//!     fn my_guard(&mut self,
+//!            state: MyMachineState,
+//!            event: &MyMachineEvent
+//!        ) -> bool;
+//! }
+//! ```
+//!
+//! ### Observers
+//! Observers have a default implementation doing nothing, see [Observing Transitions](#observing-transitions).
+//! ```
+//! # type MyMachineState=bool;
+//! # type MyMachineEvent=bool;
+//! trait MyMachineHandler {
+//!     fn on_transition(&mut self,
+//!            old_state: MyMachineState,
+//!            event: &MyMachineEvent,
+//!            new_state: MyMachineState) {}
+//!     fn on_event_ignored(&mut self,
+//!            state: MyMachineState,
+//!            event: &MyMachineEvent) {}
+//! }
+//! ```
+//!
+//!  [(back to top)](index.html)
+//!
+//! ## Statemachine interface
+//! The created statemachine struct defines several functions to interact with the statemachine.
+//!
+//! ### Statemachine definition
+//! The statemachine handler is given as a generic parameter (e.g. statemachine `Name MyStatemachine`):
+//! ```
+//! struct MyStatemachine<Handler>{
+//!     /*...*/
+//! # h:Handler
+//! }
+//! impl<Handler> MyStatemachine<Handler>{
+//!      /*...*/
+//! }
+//! ```
+//!
+//! ### Functions
+//! ##### new()
+//! ```
+//! # struct Statemachine<Handler>{ h:Handler }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn new(handler: Handler) -> Self
+//! #  {Self{h:handler}}
+//! # }
+//! ```
+//! Creates a new statemachine instance.
+//! * `handler` - The handler providing handlers and guards
+//!
+//! ---
+//! ##### restore()
+//! ```
+//! # struct StatemachineSnapshot{}
+//! # struct Statemachine<Handler>{ h:Handler }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn restore(handler: Handler, snapshot: StatemachineSnapshot) -> Self
+//! #  {Self{h:handler}}
+//! # }
+//! ```
+//! Creates a statemachine instance resuming where a snapshot was taken, see [Snapshots](#snapshots). No handler
+//! function is called, the statemachine is running.
+//! * `handler` - The handler providing handlers and guards
+//! * `snapshot` - The snapshot as returned by [`snapshot()`](#snapshot)
+//!
+//! ---
+//! ##### get_handler()
+//! ```
+//! # use std::rc::Rc;
+//! # use std::cell::RefCell;
+//! # struct Statemachine<Handler>{ h:Rc<RefCell<Handler>> }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn get_handler(&self) -> Rc<RefCell<Handler>>
+//! #  {self.h.clone()}
+//! # }
+//! ```
+//! Returns a `Rc` reference counted `RefCell` to the owned `Handler`. With option `thread_safe`, an
+//! `Arc<Mutex<Handler>>` is returned. Not available with options `no_std` and `direct_ownership`.
+//!
+//! ---
+//! #### get_handler_ref()
+//! ```
+//! # use std::rc::Rc;
+//! # use std::cell::{Ref,RefCell};
+//! # struct Statemachine<Handler>{ h:Rc<RefCell<Handler>> }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn get_handler_ref(&self) -> Ref<Handler>
+//! #  {self.h.borrow()}
+//! # }
+//! ```
+//! Returns a non-mutably borrowed reference to the owned Handler. With option `thread_safe`, a `MutexGuard` is
+//! returned. With options `no_std` and `direct_ownership`, a plain `&Handler` is returned.
+//!
+//! ---
+//! #### get_handler_mut()
+//! ```
+//! # use std::rc::Rc;
+//! # use std::cell::{RefMut,RefCell};
+//! # struct Statemachine<Handler>{ h:Rc<RefCell<Handler>> }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn get_handler_mut(&self) -> RefMut<Handler>
+//! #  {self.h.borrow_mut()}
+//! # }
+//! ```
+//! Returns a mutably borrowed reference to the owned Handler. With option `thread_safe`, a `MutexGuard` is returned.
+//! With options `no_std` and `direct_ownership`, this function takes `&mut self` and returns a plain `&mut Handler`.
+//!
+//! ---
+//! #### into_handler()
+//! ```
+//! # struct Statemachine<Handler>{ h:Handler }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn into_handler(self) -> Handler
+//! #  {self.h}
+//! # }
+//! ```
+//! Consumes the statemachine and returns the owned Handler. Only available with options `no_std` and
+//! `direct_ownership`.
+//!
+//! ---
+//! #### get_state()
+//! ```
+//! # enum StatemachineState{State}
+//! # struct Statemachine<Handler>{h:Handler}
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn get_state(&self) -> StatemachineState
+//! #  {StatemachineState::State}
+//! # }
+//! ```
+//! Returns the current state of the statemachine.
+//!
+//! ---
+//! #### event()
+//! ```
+//! # enum StatemachineEvent{Event}
+//! # enum StatemachineOutcome{Queued}
+//! # struct Statemachine<Handler>{ h:Handler }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn event(&mut self, ev: StatemachineEvent) -> StatemachineOutcome
+//! #  {StatemachineOutcome::Queued}
+//! # }
+//! ```
+//! Sends an event to the statemachine, triggering state changes and executing actions. With option `async`, this is
+//! an `async fn`.
+//! * `ev` - The event to send to the machine.
+//!
+//! Returns the outcome of processing `ev` (not of any events sent from handlers meanwhile):
+//! * `Transition{old_state, new_state}` - The event triggered a transition.
+//! * `Blocked{state}` - The current state has transitions for the event, but all their guards rejected it.
+//! * `Unexpected{state}` - The current state has no transition for the event, the unexpected event handler was called.
+//! * `Ignored{state}` - The current state has no transition for the event, and no unexpected event handler is
+//!   defined.
+//! * `Queued` - `event()` was called from inside a handler, the event will be processed after the current event.
+//! * `QueueFull(ev)` - `event()` was called from inside a handler while the event queue is full, the rejected event is
+//!   returned.
+//! * `Stopped` - The statemachine is stopped, the event was not processed.
+//! * `StepLimitExceeded{state}` - More than `MaxSteps` events were processed, the remaining queued events were
+//!   dropped. `state` is the state reached.
+//!
+//! If the statemachine was not started yet, `event()` makes it run without calling the entry handler of the initial
+//! state, see [`start()`](#start).
+//!
+//! A blocked event is passed to the unexpected event handler, too, if defined.
+//!
+//! Events sent from handlers are processed before `event()` returns, up to `MaxSteps` events if given, see
+//! [Sending Events from Handlers](#sending-events-from-handlers). If the statemachine wasn't started yet, `event()`
+//! starts it before processing the event, see [Starting and Stopping](#starting-and-stopping).
+//!
+//! Calling `event()` from inside a handler function queues the event, just like
+//! [`try_event_from_handler()`](#try_event_from_handler), returning the outcome `QueueFull` if the event queue is
+//! full. With option `thread_safe`, this applies to calls from the thread currently processing an event, calls from
+//! other threads wait until the processing is finished.
+//!
+//! ---
+//! #### start()
+//! ```
+//! # struct Statemachine<Handler>{ h:Handler }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn start(&self)
+//! #  {}
+//! # }
+//! ```
+//! Starts the statemachine, running the entry handler of the current state and processing the events sent by it, see
+//! [Starting and Stopping](#starting-and-stopping). Does nothing if the statemachine is running. Called from a
+//! handler, the statemachine is started after the current event was processed. With options
+//! `no_std` and `direct_ownership`, this function takes `&mut self`, with option `async`, it is an `async fn`.
+//!
+//! ---
+//! #### stop()
+//! ```
+//! # struct Statemachine<Handler>{ h:Handler }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn stop(&self)
+//! #  {}
+//! # }
+//! ```
+//! Stops the statemachine, running the exit handler of the current state and dropping all queued events. Does
+//! nothing if the statemachine is not running. Called from a handler, the statemachine is stopped after the current
+//! event was processed. With options `no_std` and `direct_ownership`, this function takes `&mut self`, with option
+//! `async`, it is an `async fn`.
+//!
+//! ---
+//! #### is_running()
+//! ```
+//! # struct Statemachine<Handler>{ h:Handler }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn is_running(&self) -> bool
+//! #  {false}
+//! # }
+//! ```
+//! Returns `true` if the statemachine is started and not stopped.
+//!
+//! ---
+//! #### reset()
+//! ```
+//! # struct Statemachine<Handler>{ h:Handler }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn reset(&self)
+//! #  {}
+//! # }
+//! ```
+//! Resets the statemachine to its initial state, see [Starting and Stopping](#starting-and-stopping). Runs the exit
+//! handler of the current state if the statemachine is running, drops all queued events, and runs the entry handler
+//! of the initial state, unless option `legacy_start` is given. Called from a handler, the statemachine is reset
+//! after the current event was processed. With options `no_std` and `direct_ownership`, this function takes
+//! `&mut self`, with option `async`, it is an `async fn`.
+//!
+//! ---
+//! #### event_from_handler()
+//! ```
+//! # enum StatemachineEvent{Event}
+//! # struct Statemachine<Handler>{ h:Handler }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn event_from_handler(&self, ev: StatemachineEvent)
+//! #  {}
+//! # }
+//! ```
+//! Sends an event to the statemachine *from inside a handler function*. This event will be queued and executed after
+//! the current event is completely processed, and before control is returned to the caller of [`event()`](#event).
+//! Queued events are processed in the order they were sent.
+//! * `ev` - The event to send to the machine.
+//!
+//! Panics if the queue is full, see `EventQueueCapacity`.
+//!
+//! Calling `event_from_handler()` from outside a handler function queues the event, too. It will be processed after
+//! the event passed to the next call of `event()`.
+//!
+//! Not available with options `no_std` and `direct_ownership`, see [Direct Ownership](#direct-ownership). Handlers
+//! of these statemachines post events via `post()` of their context.
+//!
+//! ---
+//! #### try_event_from_handler()
+//! ```
+//! # enum StatemachineEvent{Event}
+//! # struct StatemachineQueueFullError(StatemachineEvent);
+//! # struct Statemachine<Handler>{ h:Handler }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn try_event_from_handler(&self, ev: StatemachineEvent) -> Result<(), StatemachineQueueFullError>
+//! #  {Ok(())}
+//! # }
+//! ```
+//! Sends an event to the statemachine like [`event_from_handler()`](#event_from_handler), but returns the event in a
+//! `StatemachineQueueFullError` if the queue is full, instead of panicking. With options `no_std` and
+//! `direct_ownership`, the context provides `try_post()` instead.
+//! * `ev` - The event to send to the machine.
+//!
+//! ---
+//! #### run()
+//! ```
+//! # use std::rc::Rc;
+//! # use std::cell::RefCell;
+//! # use std::sync::mpsc::Receiver;
+//! # enum StatemachineEvent{Event}
+//! # struct Statemachine<Handler>{ h:Rc<RefCell<Handler>> }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn run(self, receiver: Receiver<StatemachineEvent>) -> Handler
+//! #  {Rc::try_unwrap(self.h).ok().unwrap().into_inner()}
+//! # }
+//! ```
+//! Processes the events received from the channel until a final state is reached or the channel is closed, see
+//! [Running as an Actor](#running-as-an-actor). Returns the handler. Panics if the handler is still shared via
+//! `get_handler()`. With option `async`, `run()` is an `async fn` taking a `<Name>EventStream` instead.
+//! * `receiver` - The channel providing the events.
+//!
+//! ---
+//! #### accepted_events()
+//! ```
+//! # enum StatemachineEventKind{Event}
+//! # struct Statemachine<Handler>{ h:Handler }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn accepted_events(&self) -> &'static [StatemachineEventKind]
+//! #  {&[]}
+//! # }
+//! ```
+//! Returns the kinds of all events that have a transition in the current state. Guards are not evaluated.
+//!
+//! ---
+//! #### can_handle()
+//! ```
+//! # enum StatemachineEvent{Event}
+//! # struct Statemachine<Handler>{ h:Handler }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn can_handle(&self, ev: &StatemachineEvent) -> bool
+//! #  {false}
+//! # }
+//! ```
+//! Returns `true` if the event would trigger a transition in the current state. Guards are evaluated, no other
+//! handler functions are called. With option `async`, this is an `async fn`.
+//! * `ev` - The event to check.
+//!
+//! ---
+//! #### history()
+//! ```
+//! # use std::cell::{Ref,RefCell};
+//! # struct StatemachineHistory{}
+//! # struct Statemachine<Handler>{ h:Handler, history:RefCell<StatemachineHistory> }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn history(&self) -> Ref<StatemachineHistory>
+//! #  {self.history.borrow()}
+//! # }
+//! ```
+//! Returns the history of the most recent transitions, see [Transition History](#transition-history). With option
+//! `thread_safe`, this returns a `MutexGuard`, with options `no_std`, `direct_ownership`, and `async`, a plain
+//! reference.
+//!
+//! Only available if `History` is given.
+//!
+//! ---
+//! #### snapshot()
+//! ```
+//! # struct StatemachineSnapshot{}
+//! # struct Statemachine<Handler>{ h:Handler }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn snapshot(&self) -> StatemachineSnapshot
+//! #  {StatemachineSnapshot{}}
+//! # }
+//! ```
+//! Returns a snapshot of the statemachine, see [Snapshots](#snapshots).
+//!
+//! ---
+//! #### random_walk()
+//! ```
+//! # struct StatemachineRandomWalk{}
+//! # struct StatemachineRandomWalkError{}
+//! # struct Statemachine<Handler>{ h:Handler }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn random_walk<I>(&self, walk: &mut StatemachineRandomWalk, steps: usize, invariant: I)
+//!     -> Result<(), StatemachineRandomWalkError>
+//! where I: FnMut(&Self) -> Result<(), String>
+//! #  {Ok(())}
+//! # }
+//! ```
+//! Sends up to `steps` random events chosen by `walk` to the statemachine, and checks `invariant` after each of them,
+//! see [Random Walks](#random-walks). With an `EventPayload`, a function creating the payload is passed before
+//! `invariant`. With options `direct_ownership` and `async`, this function takes `&mut self`, with option `async`, it
+//! is an `async fn`.
+//!
+//! Only available with option `random_walk`.
+//!
+//! ---
+//! #### coverage()
+//! ```
+//! # use std::cell::{Ref,RefCell};
+//! # struct StatemachineCoverage{}
+//! # struct Statemachine<Handler>{ h:Handler, coverage:RefCell<StatemachineCoverage> }
+//! # impl<Handler> Statemachine<Handler> {
+//! pub fn coverage(&self) -> Ref<StatemachineCoverage>
+//! #  {self.coverage.borrow()}
+//! # }
+//! ```
+//! Returns the transition coverage of the statemachine, see [Transition Coverage](#transition-coverage). With option
+//! `thread_safe`, this returns a `MutexGuard`, with options `no_std`, `direct_ownership`, and `async`, a plain
+//! reference.
+//!
+//! Only available with option `coverage`.
+//!
+//!
+//!
+//!
+#![allow(clippy::needless_doctest_main)]
+
+extern crate proc_macro;
+use proc_macro::TokenStream;
+use syn::*;
+use syn::parse::*;
+use quote::*;
+use syn::__private::TokenStream2;
+use proc_macro2::Span;
+
+// The capacity of the event queue with option no_std, if not given by EventQueueCapacity
+const DEFAULT_NO_STD_EVENT_QUEUE_CAPACITY: usize = 8;
+
+// The largest array size serde implements its traits for, limiting the capacities with options no_std and serde
+const MAX_SERDE_ARRAY_SIZE: usize = 32;
+
+// Functions of the handler trait with a default implementation, called for every event
+const OBSERVER_FUNCTIONS: [&str; 2] = ["on_transition", "on_event_ignored"];
+
+struct TypeIdents{
+    state_type: Ident,
+    event_type: Ident,
+    event_kind_type: Ident,
+    parse_error_type: Ident,
+    context_type: Ident,
+    event_queue_type: Ident,
+    queue_full_error_type: Ident,
+    outcome_type: Ident,
+    history_type: Ident,
+    history_entry_type: Ident,
+    snapshot_type: Ident,
+    lifecycle_type: Ident,
+    lifecycle_request_type: Ident,
+    handler_trait: Ident,
+    mock_handler_type: Ident,
+    mock_call_type: Ident,
+    random_walk_type: Ident,
+    random_walk_error_type: Ident,
+    coverage_type: Ident,
+    transition_coverage_type: Ident,
+    event_stream_type: Ident,
+}
+
+
+
+#[proc_macro]
+/// The `statemachine!()` macro takes as parameter the description of a statemachine in a domain-specific language.
+/// For a detailed description, please refer to the [module documentation](index.html).
+pub fn statemachine(tokens: TokenStream) -> TokenStream {
+
+    let input =parse_macro_input!(tokens as StatemachineInfo);
+
+    let StatemachineInfo{
+        sm_name,
+        initial_state,
+        event_payload_type,
+        unexpected_event_handler,
+        states,
+        events,
+        onentrys,
+        onexits,
+        guards,
+        actions,
+        state_transitions,
+        options, ..
+    } = &input;
+
+    let type_idents=TypeIdents {
+        state_type: format_ident!("{}State",sm_name),
+        event_type: format_ident!("{}Event", sm_name),
+        event_kind_type: format_ident!("{}EventKind", sm_name),
+        parse_error_type: format_ident!("{}ParseError", sm_name),
+        context_type: format_ident!("{}Context", sm_name),
+        event_queue_type: format_ident!("{}EventQueue", sm_name),
+        queue_full_error_type: format_ident!("{}QueueFullError", sm_name),
+        outcome_type: format_ident!("{}Outcome", sm_name),
+        history_type: format_ident!("{}History", sm_name),
+        history_entry_type: format_ident!("{}HistoryEntry", sm_name),
+        snapshot_type: format_ident!("{}Snapshot", sm_name),
+        lifecycle_type: format_ident!("{}Lifecycle", sm_name),
+        lifecycle_request_type: format_ident!("{}LifecycleRequest", sm_name),
+        handler_trait: format_ident!("{}Handler", sm_name),
+        mock_handler_type: format_ident!("{}MockHandler", sm_name),
+        mock_call_type: format_ident!("{}MockCall", sm_name),
+        random_walk_type: format_ident!("{}RandomWalk", sm_name),
+        random_walk_error_type: format_ident!("{}RandomWalkError", sm_name),
+        coverage_type: format_ident!("{}Coverage", sm_name),
+        transition_coverage_type: format_ident!("{}TransitionCoverage", sm_name),
+        event_stream_type: format_ident!("{}EventStream", sm_name),
+    };
+    let TypeIdents{
+        state_type, event_type, event_kind_type, parse_error_type, outcome_type, lifecycle_type, handler_trait, ..
+    }=&type_idents;
+
+    let state_definition=quote!(
+        enum #state_type {
+            #(#states),*
+        }
+    );
+
+    let event_definition = build_event_enum_definition(&type_idents, event_payload_type, events);
+    let event_kind_definition = build_event_kind_definition(&type_idents, options, events);
+    let std_crate=if options.no_std {quote!(core)} else {quote!(std)};
+    let final_states: Vec<&Ident>=state_transitions.iter()
+        .filter(|st| st.transitions.is_empty())
+        .map(|st| &st.state)
+        .collect();
+    let is_final_tokens=if final_states.is_empty() {
+        quote!(false)
+    } else {
+        quote!(matches!(*self, #(#state_type::#final_states)|*))
+    };
+    let state_name_conversions = build_name_conversions(state_type, parse_error_type, &std_crate, states);
+    let transition_table=build_transition_table(&type_idents, state_transitions);
+    let event_kind_name_conversions = build_name_conversions(event_kind_type, parse_error_type, &std_crate, events);
+
+    let context_param=build_context_param(&type_idents, options);
+    let asyncness=build_async(options);
+    let unexpected_event_handler_token=
+        if let Some(unexpected_event_handler_ident)=unexpected_event_handler {
+            quote!(#asyncness fn #unexpected_event_handler_ident(&mut self #context_param,
+                state: #state_type,
+                event: #event_kind_type);)
+        } else {
+            quote!()
+        };
+
+
+    let StateTokens{
+        state_tokens,
+        state_on_entry_tokens,
+        state_on_exit_tokens
+    } = build_state_dependent_tokens(&input,&type_idents, options, state_transitions );
+
+    let EventQueryTokens{
+        accepted_events_tokens,
+        can_handle_tokens
+    } = build_event_query_tokens(&type_idents, options, state_transitions);
+
+    let guards=build_guards_list(&type_idents,options,guards);
+    let actions=build_actions_list(&type_idents, event_payload_type, options, actions);
+    let onentrys = build_onentrys_list(&type_idents, options, onentrys);
+    let onexits = build_onexits_list(&type_idents, options, onexits);
+
+
+    let entry_trans_info_tokens=if options.entry_handler_with_transition_info {
+        quote!(,
+                old_state: #state_type,
+                event: &#event_type,
+                new_state: #state_type)
+    } else {quote!(,new_state: #state_type)};
+
+    let exit_trans_info_tokens=if options.exit_handler_with_transition_info {
+        quote!(,
+                old_state: #state_type,
+                event: &#event_type,
+                new_state: #state_type)
+    } else {quote!(,old_state: #state_type)};
+
+
+    let event_queue_definition=build_event_queue_definition(&input, &type_idents);
+    let history_definition=build_history_definition(&input, &type_idents);
+    let snapshot_definition=build_snapshot_definition(&input, &type_idents);
+    let mock_handler_definition=build_mock_handler_definition(&input, &type_idents);
+    let coverage_definition=build_coverage_definition(&input, &type_idents);
+    let scenario_tests=build_scenario_tests(&input, &type_idents);
+    let analysis_warnings=build_analysis_warnings(&input);
+    let log_dispatch=build_log(sm_name, options, quote!(debug), "dispatching event", &[
+        ("state", quote!(state)),
+        ("event", quote!(ev.kind())),
+    ]);
+    let dispatch=build_dispatch_span(sm_name, options, quote!(
+        #log_dispatch
+        #[allow(unreachable_patterns)]
+        match state {
+            #(#state_tokens),*
+        }
+    ));
+    let LayoutTokens{
+        machine_struct,
+        machine_interface,
+        runner,
+        lifecycle_requests,
+        self_mut
+    } = build_machine_layout(&input, &type_idents);
+    let (random_walk_definition, random_walk_function)=build_random_walk_tokens(&input, &type_idents, &self_mut);
+
+    let serde_derive=build_serde_derive(options);
+
+    let dot_await=build_await(options);
+    let (steps_init, steps_check)=build_step_limit_tokens(&input, &type_idents);
+    // Entry and exit handlers taking transition info can't be called on start, stop, and reset, since there is no
+    // event. With option legacy_start, the entry handler of the initial state isn't called on reset, either
+    let entry=if options.entry_handler_with_transition_info {quote!()} else {
+        quote!(self.call_on_entry(self.get_state())#dot_await;)
+    };
+    let exit=if options.exit_handler_with_transition_info {quote!()} else {
+        quote!(self.call_on_exit(self.get_state())#dot_await;)
+    };
+    let reset_entry=if options.legacy_start {quote!()} else {entry.clone()};
+    let lifecycle_functions=quote!(
+        #asyncness fn start_machine(#self_mut) {
+            if self.lifecycle() == #lifecycle_type::Running {
+                return;
+            }
+            self.set_lifecycle(#lifecycle_type::Running);
+            #entry
+        }
+
+        #asyncness fn reset_machine(#self_mut) {
+            if self.lifecycle() == #lifecycle_type::Running {
+                #exit
+            }
+            self.clear_queued_events();
+            self.set_state(#state_type::#initial_state);
+            self.set_lifecycle(#lifecycle_type::Running);
+            #reset_entry
+        }
+
+        #asyncness fn stop_machine(#self_mut) {
+            if self.lifecycle() != #lifecycle_type::Running {
+                return;
+            }
+            #exit
+            self.clear_queued_events();
+            self.set_lifecycle(#lifecycle_type::Stopped);
+        }
+    );
+
+    // Variant names are taken verbatim from the definition, a common prefix is the user's choice
+    let output=quote! (
+        #[derive(PartialEq,Clone,Debug)]
+        #serde_derive
+        #[allow(clippy::enum_variant_names)]
+        pub #state_definition
+
+        #[derive(PartialEq,Clone,Debug)]
+        #serde_derive
+        #[allow(clippy::enum_variant_names)]
+        pub #event_definition
+
+        #event_kind_definition
+
+        #state_name_conversions
+
+        impl #state_type {
+            /// Returns true if this is a final state, i.e. a state without any transitions
+            pub fn is_final(&self) -> bool {
+                #is_final_tokens
+            }
+
+            #transition_table
+        }
+
+        #event_kind_name_conversions
+
+        /// The error returned when parsing a state or event kind from an unknown name
+        #[derive(PartialEq,Eq,Clone,Debug)]
+        pub struct #parse_error_type;
+
+        impl #std_crate::fmt::Display for #parse_error_type {
+            fn fmt(&self, f: &mut #std_crate::fmt::Formatter<'_>) -> #std_crate::fmt::Result {
+                f.write_str(concat!("unknown ", stringify!(#sm_name), " state or event name"))
+            }
+        }
+
+        impl #std_crate::error::Error for #parse_error_type {}
+
+        /// The outcome of processing an event, as returned by `event()`
+        #[derive(PartialEq,Clone,Debug)]
+        pub enum #outcome_type {
+            /// The event triggered a transition from `old_state` to `new_state`
+            Transition{old_state: #state_type, new_state: #state_type},
+            /// The state has transitions for the event, but all their guards rejected it. The unexpected event
+            /// handler was called, if defined.
+            Blocked{state: #state_type},
+            /// The state has no transition for the event, and the unexpected event handler was called
+            Unexpected{state: #state_type},
+            /// The state has no transition for the event, and no unexpected event handler is defined
+            Ignored{state: #state_type},
+            /// The event was sent by calling `event()` from inside a handler. It is processed after the current
+            /// event.
+            Queued,
+            /// The event was sent by calling `event()` from inside a handler while the event queue is full. The
+            /// rejected event is returned.
+            QueueFull(#event_type),
+            /// The statemachine is stopped, the event was not processed
+            Stopped,
+            /// More than `MaxSteps` events were processed by a single call of `event()`, likely an infinite event
+            /// loop. The remaining queued events were dropped, `state` is the state reached.
+            StepLimitExceeded{state: #state_type},
+        }
+
+        /// The lifecycle of the statemachine, see `start()` and `stop()`
+        #[derive(PartialEq,Eq,Clone,Copy,Debug)]
+        #serde_derive
+        pub enum #lifecycle_type {
+            /// Neither `start()` nor `event()` was called yet
+            NotStarted,
+            /// The statemachine processes events
+            Running,
+            /// The statemachine was stopped by `stop()`, and doesn't process events
+            Stopped,
+        }
+
+        #event_queue_definition
+
+        #history_definition
+
+        #snapshot_definition
+
+        #mock_handler_definition
+
+        #random_walk_definition
+
+        #coverage_definition
+
+        #scenario_tests
+
+        #analysis_warnings
+
+        #machine_struct
+
+        impl<Handler> #sm_name<Handler>
+        where Handler: #handler_trait
+        {
+            #machine_interface
+
+            #runner
+
+            #random_walk_function
+
+            /// Returns the kinds of all events the current state has transitions for, in order of their definition.
+            /// Guards are not evaluated, so a listed event may still be blocked by its guards.
+            pub fn accepted_events(&self) -> &'static [#event_kind_type] {
+                match self.get_state() {
+                    #(#accepted_events_tokens),*
+                }
+            }
+
+            /// Returns true if the event would trigger a transition in the current state. Guards are evaluated,
+            /// but neither the state nor any other handler function is touched.
+            ///
+            /// # Arguments
+            ///
+            /// * `ev` - The event to be checked.
+            pub #asyncness fn can_handle(&self, ev: &#event_type) -> bool {
+                match self.get_state() {
+                    #(#can_handle_tokens),*
+                }
+            }
+
+            #lifecycle_functions
+
+            // Processes the events queued by handlers, and the lifecycle requests of handlers, which take precedence.
+            // Returns StepLimitExceeded if more than MaxSteps steps were taken.
+            #asyncness fn process_queued_events(#self_mut) -> Option<#outcome_type> {
+                #steps_init
+                loop {
+                    #lifecycle_requests
+                    let ev=self.next_queued_event()?;
+                    #steps_check
+                    self.process_event(ev)#dot_await;
+                }
+            }
+
+            #asyncness fn process_event(#self_mut,ev: #event_type) -> #outcome_type {
+                if self.lifecycle() == #lifecycle_type::Stopped {
+                    return #outcome_type::Stopped;
+                }
+                let state=self.get_state();
+                #dispatch
+            }
+
+            #asyncness fn call_on_entry(#self_mut #entry_trans_info_tokens)
+            {
+                #[allow(unreachable_patterns)]
+                match new_state {
+                    #(#state_on_entry_tokens),*
+                    _=>(),
+                }
+            }
+
+            #asyncness fn call_on_exit(#self_mut #exit_trans_info_tokens)
+            {
+               #[allow(unreachable_patterns)]
+               match old_state {
+                    #(#state_on_exit_tokens),*
+                    _=>(),
+                }
+            }
+        }
+
+        /// The trait to be implemented by structs to actually do work when called by the statemachine.
+        #[allow(async_fn_in_trait)]
+        pub trait #handler_trait {
+            /// Called after every transition, after the entry handler of the new state. Does nothing by default.
+            fn on_transition(&mut self, old_state: #state_type, event: &#event_type, new_state: #state_type) {
+                let _=(old_state, event, new_state);
+            }
+
+            /// Called for every event that doesn't trigger a transition, i.e. that is blocked by guards, unexpected,
+            /// or ignored. Does nothing by default.
+            fn on_event_ignored(&mut self, state: #state_type, event: &#event_type) {
+                let _=(state, event);
+            }
+
+            #unexpected_event_handler_token
+            #(#onentrys)*
+            #(#onexits)*
+            #(#guards)*
+            #(#actions)*
+        }
+    ).into();
+    output
+}
+
+// Holds the tokens that differ between the statemachine layouts: the struct definition, the functions creating the
+// statemachine, accessing handler and state, and processing events, and the receiver used by all functions that call
+// handlers
+struct LayoutTokens{
+    machine_struct: TokenStream2,
+    machine_interface: TokenStream2,
+    runner: TokenStream2,
+    lifecycle_requests: TokenStream2,
+    self_mut: TokenStream2,
+}
+
+// Builds the counter limiting the number of steps taken by a single call to event(), start(), or reset(), if MaxSteps
+// is given. The event passed to event(), or entering the state, counts as the first step, each queued event and
+// lifecycle request as another one. Returns the initialization of the counter, and the check done before each further
+// step. Exceeding the limit drops the queued events and returns StepLimitExceeded.
+fn build_step_limit_tokens(info: &StatemachineInfo, type_idents: &TypeIdents) -> (TokenStream2, TokenStream2) {
+    let Some(max_steps)=info.max_steps else {
+        return (quote!(), quote!());
+    };
+    let TypeIdents{ outcome_type, .. }=type_idents;
+    (
+        quote!(let mut steps: usize=1;),
+        quote!(
+            steps+=1;
+            if steps > #max_steps {
+                self.clear_queued_events();
+                return Some(#outcome_type::StepLimitExceeded{state: self.get_state()});
+            }
+        ),
+    )
+}
+
+// Builds the start of a statemachine not started yet, done by event() before processing the event. The statemachine
+// is started like by start(), processing the events sent by the entry handler of the initial state first, and
+// returning StepLimitExceeded if these exceed MaxSteps. With option legacy_start, the statemachine just runs, without
+// calling the entry handler, as in former versions.
+fn build_implicit_start(options: &Options, type_idents: &TypeIdents) -> TokenStream2 {
+    let TypeIdents{ lifecycle_type, .. }=type_idents;
+    let dot_await=build_await(options);
+    let start=if options.legacy_start {
+        quote!(self.set_lifecycle(#lifecycle_type::Running);)
+    } else {
+        quote!(
+            self.start_machine()#dot_await;
+            if let Some(outcome)=self.process_queued_events()#dot_await {
+                return outcome;
+            }
+        )
+    };
+    quote!(
+        if self.lifecycle() == #lifecycle_type::NotStarted {
+            #start
+        }
+    )
+}
+
+// Builds the layout-dependent parts of the statemachine. Depending on the options, the statemachine shares its
+// handler via Rc/RefCell or Arc/Mutex, or owns handler and state directly (no_std).
+fn build_machine_layout(info: &StatemachineInfo, type_idents: &TypeIdents) -> LayoutTokens {
+    if info.options.owned() {
+        build_owned_layout(info, type_idents)
+    } else {
+        build_cell_layout(info, type_idents)
+    }
+}
+
+// Builds the statemachine sharing its handler via Rc/RefCell, or Arc/Mutex for option thread_safe
+fn build_cell_layout(info: &StatemachineInfo, type_idents: &TypeIdents) -> LayoutTokens {
+    let StatemachineInfo{sm_name, initial_state, options, ..}=info;
+    let TypeIdents{
+        state_type, event_type, event_kind_type, event_queue_type, queue_full_error_type, outcome_type, history_type,
+        snapshot_type, lifecycle_type, coverage_type, ..
+    }=type_idents;
+
+    let cell_tokens=CellTokens::new(options);
+    let CellTokens{shared, cell, guard, guard_mut, handler_type_name, ..}=&cell_tokens;
+    let get_handler_doc=format!("Returns the handler owned by the statemachine as an {}", handler_type_name);
+    let (dispatch_field, dispatch_init)=cell_tokens.dispatch_fields();
+    let event_from_handler_message=format!("{}: event_from_handler() called while the event queue is full", sm_name);
+    let dispatch_start=cell_tokens.dispatch_start(quote!(
+        return match self.try_event_from_handler(ev) {
+            Ok(()) => #outcome_type::Queued,
+            Err(#queue_full_error_type(ev)) => #outcome_type::QueueFull(ev),
+        };
+    ));
+    let handler_ref_message=format!("{}: get_handler_ref() called from a handler, the handler is locked", sm_name);
+    let handler_ref_check=cell_tokens.reentrancy_check(quote!(panic!(#handler_ref_message);));
+    let handler_mut_message=format!("{}: get_handler_mut() called from a handler, the handler is locked", sm_name);
+    let handler_mut_check=cell_tokens.reentrancy_check(quote!(panic!(#handler_mut_message);));
+    let run_shared_message=format!("{}: run() can't return the handler, it is still shared via get_handler()", sm_name);
+    let handler_into_inner=cell_tokens.unwrap_cell(quote!(handler));
+    let implicit_start=build_implicit_start(options, type_idents);
+    let borrow_lifecycle=cell_tokens.borrow(quote!(self.lifecycle));
+    let borrow_handler=cell_tokens.borrow(quote!(*self.handler));
+    let borrow_handler_mut=cell_tokens.borrow_mut(quote!(*self.handler));
+    let borrow_state=cell_tokens.borrow(quote!(self.state));
+    let borrow_state_mut=cell_tokens.borrow_mut(quote!(self.state));
+    let borrow_event_queue=cell_tokens.borrow(quote!(self.event_queue));
+    let borrow_event_queue_mut=cell_tokens.borrow_mut(quote!(self.event_queue));
+    let (lifecycle_request_definition, lifecycle_request_field, lifecycle_request_init, lifecycle_functions,
+        lifecycle_requests)=build_cell_lifecycle_tokens(info, type_idents, &cell_tokens);
+    let (coverage_field, coverage_init, coverage_functions)=if options.coverage {
+        let borrow_coverage=cell_tokens.borrow(quote!(self.coverage));
+        let borrow_coverage_mut=cell_tokens.borrow_mut(quote!(self.coverage));
+        (
+            quote!(coverage: #cell<#coverage_type>,),
+            quote!(coverage: #cell::new(#coverage_type::new()),),
+            quote!(
+                /// Returns the transition coverage of this statemachine, counted since its creation
+                pub fn coverage(&self) -> #guard<#coverage_type> {
+                    #borrow_coverage
+                }
+
+                fn count_coverage(&self, transition: usize, counter: usize) {
+                    #borrow_coverage_mut.count(transition, counter);
+                }
+            ),
+        )
+    } else {(quote!(), quote!(), quote!())};
+    let has_history=info.history_capacity.is_some();
+    let (history_field, history_init, history_functions, history_restore, history_snapshot)=if has_history {
+        let borrow_history=cell_tokens.borrow(quote!(self.history));
+        let borrow_history_mut=cell_tokens.borrow_mut(quote!(self.history));
+        (
+            quote!(history: #cell<#history_type>,),
+            quote!(history: #cell::new(#history_type::new()),),
+            quote!(
+                /// Returns the history of the most recent transitions
+                pub fn history(&self) -> #guard<#history_type> {
+                    #borrow_history
+                }
+
+                fn record_history(&self, old_state: #state_type, event: #event_kind_type,
+                                  guard: Option<&'static str>, new_state: #state_type) {
+                    #borrow_history_mut.record(old_state, event, guard, new_state);
+                }
+            ),
+            quote!(history: #cell::new(#history_type::from_snapshot(snapshot.history)),),
+            quote!(history: #borrow_history.snapshot(),),
+        )
+    } else {(quote!(), quote!(), quote!(), quote!(), quote!())};
+
+    let machine_struct=quote!(
+        pub struct #sm_name<Handler> {
+            handler: #shared<#cell<Handler>>,
+            state: #cell<#state_type>,
+            event_queue: #cell<#event_queue_type>,
+            lifecycle: #cell<#lifecycle_type>,
+            #lifecycle_request_field
+            #dispatch_field
+            #history_field
+            #coverage_field
+        }
+
+        #lifecycle_request_definition
+    );
+
+    let machine_interface=quote!(
+        /// Creates a new instance of this statemachine.
+        ///
+        /// # Arguments
+        ///
+        /// * `handler` - A struct implementing the Handler trait of this statemachine. The statemachine takes
+        /// ownership
+        pub fn new(handler: Handler)->#sm_name<Handler>{
+            let h=#shared::new(#cell::new(handler));
+            #sm_name{
+                handler:h,
+                state:#cell::new(#state_type::#initial_state),
+                event_queue:#cell::new(#event_queue_type::new()),
+                lifecycle:#cell::new(#lifecycle_type::NotStarted),
+                #lifecycle_request_init
+                #dispatch_init
+                #history_init
+                #coverage_init
+            }
+        }
+
+        /// Creates an instance of this statemachine from a snapshot, resuming where the snapshot was taken. No
+        /// handler function is called.
+        ///
+        /// # Arguments
+        ///
+        /// * `handler` - A struct implementing the Handler trait of this statemachine. The statemachine takes
+        /// ownership
+        /// * `snapshot` - The snapshot as returned by `snapshot()`
+        pub fn restore(handler: Handler, snapshot: #snapshot_type)->#sm_name<Handler>{
+            #sm_name{
+                handler:#shared::new(#cell::new(handler)),
+                state:#cell::new(snapshot.state),
+                event_queue:#cell::new(#event_queue_type::from_snapshot(snapshot.pending_events)),
+                lifecycle:#cell::new(snapshot.lifecycle),
+                #lifecycle_request_init
+                #dispatch_init
+                #history_restore
+                #coverage_init
+            }
+        }
+
+        /// Returns a snapshot of the statemachine, allowing to resume it later by `restore()`. Should be called
+        /// while no event is processed.
+        pub fn snapshot(&self) -> #snapshot_type {
+            #snapshot_type{
+                state:self.get_state(),
+                lifecycle:self.lifecycle(),
+                pending_events:#borrow_event_queue.snapshot(),
+                #history_snapshot
+            }
+        }
+
+        #[doc = #get_handler_doc]
+        pub fn get_handler(&self) -> #shared<#cell<Handler>> {
+            self.handler.clone()
+        }
+
+        /// Returns a non-mutable reference to the owned handler
+        pub fn get_handler_ref(&self) -> #guard<Handler>{
+            #handler_ref_check
+            self.handler_ref()
+        }
+
+        /// Returns a mutable reference to the owned handler
+        pub fn get_handler_mut(&self) -> #guard_mut<Handler> {
+            #handler_mut_check
+            self.handler_mut()
+        }
+
+        fn handler_ref(&self) -> #guard<Handler>{
+            #borrow_handler
+        }
+
+        fn handler_mut(&self) -> #guard_mut<Handler> {
+            #borrow_handler_mut
+        }
+
+        /// Returns the current state the statemachine is in
+        pub fn get_state(&self)-> #state_type {
+            (*#borrow_state).clone()
+        }
+
+        /// Processes an event. This is the main function of this statemachine, implementing the actual behavior.
+        ///
+        /// # Arguments
+        ///
+        /// * `ev` - The event to be processed. The statemachine takes ownership. Subsequent calls to handler
+        /// functions will use references, only, to avoid cloning an potentially large event payload.
+        ///
+        /// If any handler calls "event_from_handler()" or "event()" in the processing of `ev`, the new event will be
+        /// processed directly after `ev`.
+        ///
+        /// Returns the outcome of processing `ev`, or `Queued` if called from a handler. If the event queue is full
+        /// in this case, the event is returned in `QueueFull`.
+        pub fn event(&self,ev: #event_type) -> #outcome_type {
+            #dispatch_start
+            #implicit_start
+            let outcome=self.process_event(ev);
+            self.process_queued_events().unwrap_or(outcome)
+        }
+
+        /// Enqueues an event for processing. This function is intended to be used from handlers, only. Events are
+        /// processed in the order they were enqueued.
+        ///
+        /// # Arguments
+        ///
+        /// * `ev` - The event to be enqueued. The statemachine takes ownership.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `EventQueueCapacity` is given and the event queue is full, see `try_event_from_handler()`.
+        pub fn event_from_handler(&self,ev: #event_type) {
+            if self.try_event_from_handler(ev).is_err() {
+                panic!(#event_from_handler_message);
+            }
+        }
+
+        /// Enqueues an event for processing like `event_from_handler()`, but returns the event if the event queue
+        /// is full. Queued events are never dropped in favor of the new event.
+        ///
+        /// # Arguments
+        ///
+        /// * `ev` - The event to be enqueued. The statemachine takes ownership.
+        ///
+        /// # Errors
+        ///
+        /// Returns the event in an error if the event queue is full.
+        pub fn try_event_from_handler(&self,ev: #event_type) -> Result<(), #queue_full_error_type> {
+            #borrow_event_queue_mut.push(ev)
+        }
+
+        #lifecycle_functions
+
+        /// Returns true if the statemachine was started and not stopped yet
+        pub fn is_running(&self) -> bool {
+            self.lifecycle() == #lifecycle_type::Running
+        }
+
+        fn lifecycle(&self) -> #lifecycle_type {
+            *#borrow_lifecycle
+        }
+
+        fn next_queued_event(&self) -> Option<#event_type> {
+            #borrow_event_queue_mut.pop()
+        }
+
+        fn clear_queued_events(&self) {
+            #borrow_event_queue_mut.clear();
+        }
+
+        fn set_state(&self, state: #state_type) {
+            *#borrow_state_mut=state;
+        }
+
+        #history_functions
+        #coverage_functions
+    );
+
+    let runner=quote!(
+        /// Runs the statemachine as an actor, processing the events received from a channel. Returns when the
+        /// statemachine reaches a final state (a state without any transitions), or when the channel is closed.
+        ///
+        /// # Arguments
+        ///
+        /// * `receiver` - The receiving end of the channel providing the events.
+        ///
+        /// Returns the handler. Panics if the handler is still shared, i.e. if a reference returned by
+        /// `get_handler()` is still alive.
+        pub fn run(self, receiver: std::sync::mpsc::Receiver<#event_type>) -> Handler {
+            while !self.get_state().is_final() {
+                match receiver.recv() {
+                    Ok(ev) => {self.event(ev);},
+                    Err(_) => break,
+                }
+            }
+            match #shared::try_unwrap(self.handler) {
+                Ok(handler) => #handler_into_inner,
+                Err(_) => panic!(#run_shared_message),
+            }
+        }
+    );
+
+    LayoutTokens{machine_struct, machine_interface, runner, lifecycle_requests, self_mut: quote!(&self)}
+}
+
+// Builds the lifecycle functions start(), stop(), and reset() of the statemachine sharing its handler. Called from a
+// handler, these functions can't enter or exit a state while the handler is borrowed, so they record a request,
+// which is carried out after the event being processed. Returns the request type, field, and initialization, the
+// functions, and the statements carrying out a request, to be run before each queued event.
+fn build_cell_lifecycle_tokens(info: &StatemachineInfo, type_idents: &TypeIdents, cell_tokens: &CellTokens)
+    -> (TokenStream2, TokenStream2, TokenStream2, TokenStream2, TokenStream2)
+{
+    let StatemachineInfo{options, ..}=info;
+    let TypeIdents{ lifecycle_type, lifecycle_request_type, .. }=type_idents;
+    let CellTokens{cell, ..}=cell_tokens;
+    let (_, steps_check)=build_step_limit_tokens(info, type_idents);
+    let (entry_note, exit_note)=build_lifecycle_notes(options);
+    let borrow_lifecycle_mut=cell_tokens.borrow_mut(quote!(self.lifecycle));
+    let borrow_lifecycle_request_mut=cell_tokens.borrow_mut(quote!(self.lifecycle_request));
+    let request_on_reentrant=|request: TokenStream2| cell_tokens.dispatch_start(quote!(
+        *#borrow_lifecycle_request_mut=Some(#lifecycle_request_type::#request);
+        return;
+    ));
+    let start_dispatch_start=request_on_reentrant(quote!(Start));
+    let stop_dispatch_start=request_on_reentrant(quote!(Stop));
+    let reset_dispatch_start=request_on_reentrant(quote!(Reset));
+
+    (
+        quote!(
+            /// A call of `start()`, `stop()`, or `reset()` from a handler, carried out after the current event
+            #[derive(Clone,Copy)]
+            enum #lifecycle_request_type {
+                Start,
+                Stop,
+                Reset,
+            }
+        ),
+        quote!(lifecycle_request: #cell<Option<#lifecycle_request_type>>,),
+        quote!(lifecycle_request: #cell::new(None),),
+        quote!(
+            /// Starts the statemachine, calling the entry handler of the current state and processing the events
+            /// posted by it. Does nothing if the statemachine is already running. A stopped statemachine is restarted
+            /// in its current state. Called from a handler, the statemachine is started after the current event
+            /// was processed.
+            #entry_note
+            pub fn start(&self) {
+                #start_dispatch_start
+                self.start_machine();
+                self.process_queued_events();
+            }
+
+            /// Stops the statemachine, calling the exit handler of the current state. Does nothing if the
+            /// statemachine is not running. Events sent to a stopped statemachine are not processed, events queued
+            /// are dropped. Called from a handler, the statemachine is stopped after the current event was processed.
+            #exit_note
+            pub fn stop(&self) {
+                #stop_dispatch_start
+                self.stop_machine();
+            }
+
+            /// Resets the statemachine to its initial state. Calls the exit handler of the current state if the
+            /// statemachine is running, drops all queued events, and enters the initial state, calling its entry
+            /// handler and processing the events posted by it. The statemachine is running afterwards. Called from a
+            /// handler, the statemachine is reset after the current event was processed.
+            #exit_note
+            #entry_note
+            pub fn reset(&self) {
+                #reset_dispatch_start
+                self.reset_machine();
+                self.process_queued_events();
+            }
+
+            fn set_lifecycle(&self, lifecycle: #lifecycle_type) {
+                *#borrow_lifecycle_mut=lifecycle;
+            }
+        ),
+        quote!(
+            let request=#borrow_lifecycle_request_mut.take();
+            if let Some(request)=request {
+                #steps_check
+                match request {
+                    #lifecycle_request_type::Start => self.start_machine(),
+                    #lifecycle_request_type::Stop => self.stop_machine(),
+                    #lifecycle_request_type::Reset => self.reset_machine(),
+                }
+                continue;
+            }
+        ),
+    )
+}
+
+// Builds the doc lines added to the lifecycle functions calling entry or exit handlers, if these take transition info.
+// Such handlers aren't called by start(), stop(), and reset(), since there is no event.
+fn build_lifecycle_notes(options: &Options) -> (TokenStream2, TokenStream2) {
+    let entry_note=if options.entry_handler_with_transition_info {
+        quote!(
+            ///
+            /// The entry handlers take transition info, so they aren't called, since there is no event.
+        )
+    } else {quote!()};
+    let exit_note=if options.exit_handler_with_transition_info {
+        quote!(
+            ///
+            /// The exit handlers take transition info, so they aren't called, since there is no event.
+        )
+    } else {quote!()};
+    (entry_note, exit_note)
+}
+
+// Builds the statemachine owning handler and state directly, without Rc, RefCell, or any other allocation. All
+// functions calling handlers take &mut self. Handlers post events via the context passed to them.
+fn build_owned_layout(info: &StatemachineInfo, type_idents: &TypeIdents) -> LayoutTokens {
+    let StatemachineInfo{sm_name, initial_state, options, ..}=info;
+    let TypeIdents{
+        state_type, event_type, event_kind_type, context_type, event_queue_type, queue_full_error_type, outcome_type,
+        history_type, snapshot_type, lifecycle_type, event_stream_type, coverage_type, ..
+    }=type_idents;
+    let post_message=format!("{}: post() called while the event queue is full", sm_name);
+    let implicit_start=build_implicit_start(options, type_idents);
+    let asyncness=build_async(options);
+    let dot_await=build_await(options);
+    let (coverage_field, coverage_init, coverage_functions)=if options.coverage {
+        (
+            quote!(coverage: #coverage_type,),
+            quote!(coverage: #coverage_type::new(),),
+            quote!(
+                /// Returns the transition coverage of this statemachine, counted since its creation
+                pub fn coverage(&self) -> &#coverage_type {
+                    &self.coverage
+                }
+
+                fn count_coverage(&mut self, transition: usize, counter: usize) {
+                    self.coverage.count(transition, counter);
+                }
+            ),
+        )
+    } else {(quote!(), quote!(), quote!())};
+    let has_history=info.history_capacity.is_some();
+    let (history_field, history_init, history_functions, history_restore, history_snapshot)=if has_history {
+        (
+            quote!(history: #history_type,),
+            quote!(history: #history_type::new(),),
+            quote!(
+                /// Returns the history of the most recent transitions
+                pub fn history(&self) -> &#history_type {
+                    &self.history
+                }
+
+                fn record_history(&mut self, old_state: #state_type, event: #event_kind_type,
+                                  guard: Option<&'static str>, new_state: #state_type) {
+                    self.history.record(old_state, event, guard, new_state);
+                }
+            ),
+            quote!(history: #history_type::from_snapshot(snapshot.history),),
+            quote!(history: self.history.snapshot(),),
+        )
+    } else {(quote!(), quote!(), quote!(), quote!(), quote!())};
+
+    let (entry_note, exit_note)=build_lifecycle_notes(options);
+    let lifecycle_functions=quote!(
+        /// Starts the statemachine, calling the entry handler of the current state and processing the events
+        /// posted by it. Does nothing if the statemachine is already running. A stopped statemachine is restarted
+        /// in its current state.
+        #entry_note
+        pub #asyncness fn start(&mut self) {
+            self.start_machine()#dot_await;
+            self.process_queued_events()#dot_await;
+        }
+
+        /// Stops the statemachine, calling the exit handler of the current state. Does nothing if the
+        /// statemachine is not running. Events sent to a stopped statemachine are not processed, events queued
+        /// are dropped.
+        #exit_note
+        pub #asyncness fn stop(&mut self) {
+            self.stop_machine()#dot_await;
+        }
+
+        /// Resets the statemachine to its initial state. Calls the exit handler of the current state if the
+        /// statemachine is running, drops all queued events, and enters the initial state, calling its entry
+        /// handler and processing the events posted by it. The statemachine is running afterwards.
+        #exit_note
+        #entry_note
+        pub #asyncness fn reset(&mut self) {
+            self.reset_machine()#dot_await;
+            self.process_queued_events()#dot_await;
+        }
+
+        fn set_lifecycle(&mut self, lifecycle: #lifecycle_type) {
+            self.lifecycle=lifecycle;
+        }
+    );
+
+    let event_stream_definition=if options.async_handlers {
+        quote!(
+            /// An asynchronous source of events, consumed by `run()`. It mirrors `poll_next()` of the `Stream` trait
+            /// of the `futures` crate, so stream and channel types can implement it by delegating to their own
+            /// polling function.
+            pub trait #event_stream_type {
+                /// Polls for the next event, returning `Poll::Ready(None)` when the stream is exhausted
+                fn poll_next(self: core::pin::Pin<&mut Self>, cx: &mut core::task::Context<'_>)
+                    -> core::task::Poll<Option<#event_type>>;
+            }
+        )
+    } else {quote!()};
+
+    let machine_struct=quote!(
+        pub struct #sm_name<Handler> {
+            handler: Handler,
+            state: #state_type,
+            context: #context_type,
+            lifecycle: #lifecycle_type,
+            #history_field
+            #coverage_field
+        }
+
+        /// The context passed to handler functions, allowing them to post events to the statemachine
+        pub struct #context_type {
+            event_queue: #event_queue_type,
+        }
+
+        impl #context_type {
+            /// Posts an event to the statemachine. The event is processed after the current event is completely
+            /// processed, and before control is returned to the caller of `event()`. Events are processed in the
+            /// order they were posted.
+            ///
+            /// # Arguments
+            ///
+            /// * `ev` - The event to be posted. The statemachine takes ownership.
+            ///
+            /// # Panics
+            ///
+            /// Panics if the event queue is full, see `try_post()`.
+            pub fn post(&mut self, ev: #event_type) {
+                if self.try_post(ev).is_err() {
+                    panic!(#post_message);
+                }
+            }
+
+            /// Posts an event to the statemachine like `post()`, but returns the event if the event queue is full.
+            /// Queued events are never dropped in favor of the new event.
+            ///
+            /// # Arguments
+            ///
+            /// * `ev` - The event to be posted. The statemachine takes ownership.
+            ///
+            /// # Errors
+            ///
+            /// Returns the event in an error if the event queue is full.
+            pub fn try_post(&mut self, ev: #event_type) -> Result<(), #queue_full_error_type> {
+                self.event_queue.push(ev)
+            }
+        }
+
+        #event_stream_definition
+    );
+
+    let machine_interface=quote!(
+        /// Creates a new instance of this statemachine.
+        ///
+        /// # Arguments
+        ///
+        /// * `handler` - A struct implementing the Handler trait of this statemachine. The statemachine takes
+        /// ownership
+        pub fn new(handler: Handler)->#sm_name<Handler>{
+            #sm_name{
+                handler,
+                state:#state_type::#initial_state,
+                context:#context_type{event_queue:#event_queue_type::new()},
+                lifecycle:#lifecycle_type::NotStarted,
+                #history_init
+                #coverage_init
+            }
+        }
+
+        /// Creates an instance of this statemachine from a snapshot, resuming where the snapshot was taken. No
+        /// handler function is called.
+        ///
+        /// # Arguments
+        ///
+        /// * `handler` - A struct implementing the Handler trait of this statemachine. The statemachine takes
+        /// ownership
+        /// * `snapshot` - The snapshot as returned by `snapshot()`
+        pub fn restore(handler: Handler, snapshot: #snapshot_type)->#sm_name<Handler>{
+            #sm_name{
+                handler,
+                state:snapshot.state,
+                context:#context_type{event_queue:#event_queue_type::from_snapshot(snapshot.pending_events)},
+                lifecycle:snapshot.lifecycle,
+                #history_restore
+                #coverage_init
+            }
+        }
+
+        /// Returns a snapshot of the statemachine, allowing to resume it later by `restore()`
+        pub fn snapshot(&self) -> #snapshot_type {
+            #snapshot_type{
+                state:self.state.clone(),
+                lifecycle:self.lifecycle,
+                pending_events:self.context.event_queue.snapshot(),
+                #history_snapshot
+            }
+        }
+
+        /// Returns a non-mutable reference to the owned handler
+        pub fn get_handler_ref(&self) -> &Handler {
+            &self.handler
+        }
+
+        /// Returns a mutable reference to the owned handler
+        pub fn get_handler_mut(&mut self) -> &mut Handler {
+            &mut self.handler
+        }
+
+        /// Consumes the statemachine and returns the owned handler
+        pub fn into_handler(self) -> Handler {
+            self.handler
+        }
+
+        /// Returns the current state the statemachine is in
+        pub fn get_state(&self)-> #state_type {
+            self.state.clone()
+        }
+
+        /// Processes an event. This is the main function of this statemachine, implementing the actual behavior.
+        ///
+        /// # Arguments
+        ///
+        /// * `ev` - The event to be processed. The statemachine takes ownership. Subsequent calls to handler
+        /// functions will use references, only, to avoid cloning an potentially large event payload.
+        ///
+        /// If any handler posts an event to its context in the processing of `ev`, the new event will be processed
+        /// directly after `ev`.
+        ///
+        /// Returns the outcome of processing `ev`.
+        pub #asyncness fn event(&mut self,ev: #event_type) -> #outcome_type {
+            #implicit_start
+            let outcome=self.process_event(ev)#dot_await;
+            self.process_queued_events()#dot_await.unwrap_or(outcome)
+        }
+
+        #lifecycle_functions
+
+        /// Returns true if the statemachine was started and not stopped yet
+        pub fn is_running(&self) -> bool {
+            self.lifecycle == #lifecycle_type::Running
+        }
+
+        fn lifecycle(&self) -> #lifecycle_type {
+            self.lifecycle
+        }
+
+        fn next_queued_event(&mut self) -> Option<#event_type> {
+            self.context.event_queue.pop()
+        }
+
+        fn clear_queued_events(&mut self) {
+            self.context.event_queue.clear();
+        }
+
+        fn set_state(&mut self, state: #state_type) {
+            self.state=state;
+        }
+
+        #history_functions
+        #coverage_functions
+    );
+
+    let runner=if options.async_handlers {
+        quote!(
+            /// Runs the statemachine as an actor, processing the events received from an asynchronous event source.
+            /// Returns when the statemachine reaches a final state (a state without any transitions), or when the
+            /// event source is exhausted.
+            ///
+            /// # Arguments
+            ///
+            /// * `stream` - The stream providing the events.
+            ///
+            /// Returns the handler.
+            pub async fn run<S: #event_stream_type>(mut self, stream: S) -> Handler {
+                let mut stream=core::pin::pin!(stream);
+                while !self.state.is_final() {
+                    match core::future::poll_fn(|cx| #event_stream_type::poll_next(stream.as_mut(), cx)).await {
+                        Some(ev) => {self.event(ev).await;},
+                        None => break,
+                    }
+                }
+                self.handler
+            }
+        )
+    } else if !options.no_std {
+        quote!(
+            /// Runs the statemachine as an actor, processing the events received from a channel. Returns when the
+            /// statemachine reaches a final state (a state without any transitions), or when the channel is closed.
+            ///
+            /// # Arguments
+            ///
+            /// * `receiver` - The receiving end of the channel providing the events.
+            ///
+            /// Returns the handler.
+            pub fn run(mut self, receiver: std::sync::mpsc::Receiver<#event_type>) -> Handler {
+                while !self.state.is_final() {
+                    match receiver.recv() {
+                        Ok(ev) => {self.event(ev);},
+                        Err(_) => break,
+                    }
+                }
+                self.handler
+            }
+        )
+    } else {quote!()};
 
+    LayoutTokens{machine_struct, machine_interface, runner, lifecycle_requests: quote!(), self_mut: quote!(&mut self)}
+}
 
-extern crate proc_macro;
-use proc_macro::TokenStream;
-use syn::*;
-use syn::parse::*;
-use quote::*;
-use syn::__private::TokenStream2;
+// Builds the FIFO queue holding the events sent from handlers, and the error returned if the queue is full. With
+// option no_std, the queue is a ring buffer stored inline, otherwise a VecDeque.
+fn build_event_queue_definition(info: &StatemachineInfo, type_idents: &TypeIdents) -> TokenStream2 {
+    let StatemachineInfo{sm_name, options, event_queue_capacity, ..}=info;
+    let TypeIdents{ event_type, event_queue_type, queue_full_error_type, .. }=type_idents;
+    let std_crate=if options.no_std {quote!(core)} else {quote!(std)};
 
-struct TypeIdents{
-    state_type: Ident,
-    event_type: Ident,
-}
+    let error_definition=quote!(
+        /// The error returned when sending an event from a handler while the event queue is full. Holds the
+        /// rejected event.
+        #[derive(PartialEq,Clone,Debug)]
+        pub struct #queue_full_error_type(pub #event_type);
 
+        impl #std_crate::fmt::Display for #queue_full_error_type {
+            fn fmt(&self, f: &mut #std_crate::fmt::Formatter<'_>) -> #std_crate::fmt::Result {
+                f.write_str(concat!(stringify!(#sm_name), " event queue is full"))
+            }
+        }
 
+        impl #std_crate::error::Error for #queue_full_error_type {}
+    );
 
-#[proc_macro]
-/// The `statemachine!()` macro takes as parameter the description of a statemachine in a domain-specific language.
-/// For a detailed description, please refer to the [module documentation](index.html).
-pub fn statemachine(tokens: TokenStream) -> TokenStream {
+    let queue_definition=if options.no_std {
+        let capacity=event_queue_capacity.unwrap_or(DEFAULT_NO_STD_EVENT_QUEUE_CAPACITY);
+        quote!(
+            struct #event_queue_type {
+                events: [Option<#event_type>; #capacity],
+                head: usize,
+                len: usize,
+            }
 
-    let input =parse_macro_input!(tokens as StatemachineInfo);
+            impl #event_queue_type {
+                fn new() -> Self {
+                    #event_queue_type{events: core::array::from_fn(|_| None), head: 0, len: 0}
+                }
 
-    let StatemachineInfo{
-        sm_name,
-        initial_state,
-        event_payload_type,
-        unexpected_event_handler,
-        states,
-        events,
-        onentrys,
-        onexits,
-        guards,
-        actions,
-        state_transitions,
-        options, ..
-    } = &input;
+                fn push(&mut self, ev: #event_type) -> Result<(), #queue_full_error_type> {
+                    if self.len == #capacity {
+                        return Err(#queue_full_error_type(ev));
+                    }
+                    self.events[(self.head + self.len) % #capacity]=Some(ev);
+                    self.len+=1;
+                    Ok(())
+                }
 
-    let type_idents=TypeIdents {
-        state_type: format_ident!("{}State",sm_name),
-        event_type: format_ident!("{}Event", sm_name),
+                fn pop(&mut self) -> Option<#event_type> {
+                    if self.len == 0 {
+                        return None;
+                    }
+                    let ev=self.events[self.head].take();
+                    self.head=(self.head + 1) % #capacity;
+                    self.len-=1;
+                    ev
+                }
+
+                fn clear(&mut self) {
+                    *self=Self::new();
+                }
+
+                fn snapshot(&self) -> [Option<#event_type>; #capacity] {
+                    core::array::from_fn(|i| if i < self.len {
+                        self.events[(self.head + i) % #capacity].clone()
+                    } else {
+                        None
+                    })
+                }
+
+                fn from_snapshot(events: [Option<#event_type>; #capacity]) -> Self {
+                    let mut queue=Self::new();
+                    for ev in events.into_iter().flatten() {
+                        queue.events[queue.len]=Some(ev);
+                        queue.len+=1;
+                    }
+                    queue
+                }
+            }
+        )
+    } else {
+        let capacity_check=if let Some(capacity)=event_queue_capacity {
+            quote!(
+                if self.events.len() == #capacity {
+                    return Err(#queue_full_error_type(ev));
+                }
+            )
+        } else {quote!()};
+        quote!(
+            struct #event_queue_type {
+                events: std::collections::VecDeque<#event_type>,
+            }
+
+            impl #event_queue_type {
+                fn new() -> Self {
+                    #event_queue_type{events: std::collections::VecDeque::new()}
+                }
+
+                fn push(&mut self, ev: #event_type) -> Result<(), #queue_full_error_type> {
+                    #capacity_check
+                    self.events.push_back(ev);
+                    Ok(())
+                }
+
+                fn pop(&mut self) -> Option<#event_type> {
+                    self.events.pop_front()
+                }
+
+                fn clear(&mut self) {
+                    self.events.clear();
+                }
+
+                fn snapshot(&self) -> Vec<#event_type> {
+                    self.events.iter().cloned().collect()
+                }
+
+                fn from_snapshot(events: Vec<#event_type>) -> Self {
+                    #event_queue_type{events: events.into()}
+                }
+            }
+        )
     };
-    let TypeIdents{ state_type, event_type }=&type_idents;
 
-    let handler_trait =format_ident!("{}Handler",sm_name);
+    quote!(
+        #error_definition
+
+        #queue_definition
+    )
+}
 
-    let state_definition=quote!(
-        enum #state_type {
-            #(#states),*
+// Builds the snapshot of the statemachine, holding the current state, the lifecycle, the queued events and, if
+// History is given, the transition history. With option no_std, the queued events and the history are held in arrays
+// of the respective capacity, filled from the start, otherwise in Vecs.
+fn build_snapshot_definition(info: &StatemachineInfo, type_idents: &TypeIdents) -> TokenStream2 {
+    let StatemachineInfo{options, event_queue_capacity, history_capacity, ..}=info;
+    let TypeIdents{ state_type, event_type, history_entry_type, snapshot_type, lifecycle_type, .. }=type_idents;
+    let serde_derive=build_serde_derive(options);
+
+    let pending_events_type=if options.no_std {
+        let capacity=event_queue_capacity.unwrap_or(DEFAULT_NO_STD_EVENT_QUEUE_CAPACITY);
+        quote!([Option<#event_type>; #capacity])
+    } else {
+        quote!(Vec<#event_type>)
+    };
+    let history_field=match history_capacity {
+        Some(capacity) => {
+            let history_type=if options.no_std {
+                quote!([Option<#history_entry_type>; #capacity])
+            } else {
+                quote!(Vec<#history_entry_type>)
+            };
+            quote!(
+                /// The transition history, from oldest to most recent
+                pub history: #history_type,
+            )
         }
-    );
+        None => quote!(),
+    };
 
-    let event_definition = build_event_enum_definition(&type_idents, event_payload_type, events);
+    quote!(
+        /// A snapshot of the statemachine, as returned by `snapshot()`. Allows to resume the statemachine later by
+        /// `restore()`.
+        #[derive(PartialEq,Clone,Debug)]
+        #serde_derive
+        pub struct #snapshot_type {
+            /// The current state
+            pub state: #state_type,
+            /// Whether the statemachine was started or stopped
+            pub lifecycle: #lifecycle_type,
+            /// The events sent from handlers that are not processed yet, in order of processing
+            pub pending_events: #pending_events_type,
+            #history_field
+        }
+    )
+}
 
-    let unexpected_event_handler_token=
-        if let Some(unexpected_event_handler_ident)=unexpected_event_handler {
-            quote!(fn #unexpected_event_handler_ident(&mut self,
-                state: #state_type,
-                event: &#event_type);)
+// Builds the ring buffer recording the most recent transitions, if History is given. The timestamp of entries is
+// not available with option no_std.
+fn build_history_definition(info: &StatemachineInfo, type_idents: &TypeIdents) -> TokenStream2 {
+    let StatemachineInfo{options, history_capacity, guards, ..}=info;
+    let Some(capacity)=history_capacity else {
+        return quote!();
+    };
+    let TypeIdents{ state_type, event_kind_type, history_type, history_entry_type, .. }=type_idents;
+
+    let snapshot_functions=if options.no_std {
+        quote!(
+            fn snapshot(&self) -> [Option<#history_entry_type>; #capacity] {
+                core::array::from_fn(|i| if i < self.len {
+                    self.entries[(self.head + i) % #capacity].clone()
+                } else {
+                    None
+                })
+            }
+
+            fn from_snapshot(entries: [Option<#history_entry_type>; #capacity]) -> Self {
+                let mut history=Self::new();
+                entries.into_iter().flatten().for_each(|entry| history.push(entry));
+                history
+            }
+        )
+    } else {
+        quote!(
+            fn snapshot(&self) -> Vec<#history_entry_type> {
+                self.iter().cloned().collect()
+            }
+
+            fn from_snapshot(entries: Vec<#history_entry_type>) -> Self {
+                let mut history=Self::new();
+                entries.into_iter().for_each(|entry| history.push(entry));
+                history
+            }
+        )
+    };
+
+    let (timestamp_field, timestamp_init)=if options.no_std {
+        (quote!(), quote!())
+    } else {
+        (
+            quote!(
+                /// The time of the transition
+                pub timestamp: std::time::SystemTime,
+            ),
+            quote!(timestamp: std::time::SystemTime::now(),),
+        )
+    };
+
+    // Guard names are &'static str, which serde would borrow from the input. So history entries are deserialized
+    // via a mirror struct owning the guard name, which is mapped to the name of the defined guard.
+    let (serde_derive, entry_deserialization)=if options.serde {
+        let guard_names: Vec<String>=guards.iter().map(|g| g.to_string()).collect();
+        let (mirror_generics, mirror_guard_type)=if options.no_std {
+            (quote!(<'a>), quote!(Option<&'a str>))
         } else {
-            quote!()
+            (quote!(), quote!(Option<std::string::String>))
+        };
+        let (mirror_timestamp_field, timestamp_copy)=if options.no_std {
+            (quote!(), quote!())
+        } else {
+            (quote!(timestamp: std::time::SystemTime,), quote!(timestamp: mirror.timestamp,))
         };
+        let entry_name=history_entry_type.to_string();
+        (
+            quote!(#[derive(::serde::Serialize)]),
+            quote!(
+                const _: () = {
+                    #[derive(::serde::Deserialize)]
+                    #[serde(rename = #entry_name)]
+                    struct Mirror #mirror_generics {
+                        seq: u64,
+                        #mirror_timestamp_field
+                        old_state: #state_type,
+                        event: #event_kind_type,
+                        guard: #mirror_guard_type,
+                        new_state: #state_type,
+                    }
+
+                    impl<'de> ::serde::Deserialize<'de> for #history_entry_type {
+                        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+                        where D: ::serde::Deserializer<'de>
+                        {
+                            const GUARDS: &[&str]=&[#(#guard_names),*];
+                            let mirror=Mirror::deserialize(deserializer)?;
+                            let guard=match mirror.guard {
+                                None => None,
+                                Some(guard) => match GUARDS.iter().find(|name| **name == guard) {
+                                    Some(name) => Some(*name),
+                                    None => return Err(::serde::de::Error::unknown_variant(&guard, GUARDS)),
+                                },
+                            };
+                            Ok(#history_entry_type{
+                                seq: mirror.seq,
+                                #timestamp_copy
+                                old_state: mirror.old_state,
+                                event: mirror.event,
+                                guard,
+                                new_state: mirror.new_state,
+                            })
+                        }
+                    }
+                };
+            ),
+        )
+    } else {(quote!(), quote!())};
 
+    quote!(
+        /// An entry of the transition history
+        #[derive(PartialEq,Clone,Debug)]
+        #serde_derive
+        pub struct #history_entry_type {
+            /// The number of the transition, counting all transitions since the statemachine was created
+            pub seq: u64,
+            #timestamp_field
+            /// The state before the transition
+            pub old_state: #state_type,
+            /// The kind of the event triggering the transition
+            pub event: #event_kind_type,
+            /// The name of the guard accepting the transition, if the transition is guarded
+            pub guard: Option<&'static str>,
+            /// The state after the transition
+            pub new_state: #state_type,
+        }
 
-    let StateTokens{
-        state_tokens,
-        state_on_entry_tokens,
-        state_on_exit_tokens
-    } = build_state_dependent_tokens(&input,&type_idents, options, state_transitions );
+        #entry_deserialization
 
-    let guards=build_guards_list(&type_idents,options,&guards);
-    let actions=build_actions_list(&type_idents, event_payload_type, options, &actions);
-    let onentrys = build_onentrys_list(&type_idents, options, &onentrys);
-    let onexits = build_onexits_list(&type_idents, options, &onexits);
+        /// The history of the most recent transitions. Holds the number of entries given by `History`, older
+        /// entries are dropped.
+        pub struct #history_type {
+            entries: [Option<#history_entry_type>; #capacity],
+            head: usize,
+            len: usize,
+            next_seq: u64,
+        }
 
+        impl #history_type {
+            fn new() -> Self {
+                #history_type{entries: core::array::from_fn(|_| None), head: 0, len: 0, next_seq: 0}
+            }
 
-    let entry_trans_info_tokens=if options.entry_handler_with_transition_info {
-        quote!(,
-                old_state: #state_type,
-                event: &#event_type,
-                new_state: #state_type)
-    } else {quote!(,new_state: #state_type)};
+            fn record(&mut self, old_state: #state_type, event: #event_kind_type, guard: Option<&'static str>,
+                      new_state: #state_type) {
+                let entry=#history_entry_type{
+                    seq: self.next_seq,
+                    #timestamp_init
+                    old_state,
+                    event,
+                    guard,
+                    new_state,
+                };
+                self.push(entry);
+            }
 
-    let exit_trans_info_tokens=if options.exit_handler_with_transition_info {
-        quote!(,
-                old_state: #state_type,
-                event: &#event_type,
-                new_state: #state_type)
-    } else {quote!(,old_state: #state_type)};
+            fn push(&mut self, entry: #history_entry_type) {
+                self.next_seq=entry.seq + 1;
+                self.entries[(self.head + self.len) % #capacity]=Some(entry);
+                if self.len == #capacity {
+                    self.head=(self.head + 1) % #capacity;
+                } else {
+                    self.len+=1;
+                }
+            }
 
+            #snapshot_functions
 
-    let output=quote! (
-        #[derive(PartialEq,Clone,Debug)]
-        pub #state_definition
+            /// Returns the number of recorded entries
+            pub fn len(&self) -> usize {
+                self.len
+            }
+
+            /// Returns true if no transition is recorded
+            pub fn is_empty(&self) -> bool {
+                self.len == 0
+            }
+
+            /// Returns an iterator over the recorded entries, from oldest to most recent
+            pub fn iter(&self) -> impl Iterator<Item=&#history_entry_type> + '_ {
+                (0..self.len).filter_map(move |i| self.entries[(self.head + i) % #capacity].as_ref())
+            }
+
+            /// Returns the most recent entry
+            pub fn last(&self) -> Option<&#history_entry_type> {
+                self.iter().last()
+            }
+        }
+    )
+}
+
+// Builds the mock handler for option mock_handler, implementing the handler trait by recording the calls of all
+// entry, exit, action, and unexpected event handlers, and returning scripted values from guards
+fn build_mock_handler_definition(info: &StatemachineInfo, type_idents: &TypeIdents) -> TokenStream2 {
+    let StatemachineInfo{event_payload_type, unexpected_event_handler, onentrys, onexits, guards, actions, scenarios,
+        options, ..}=info;
+    if !options.mock_handler && scenarios.is_empty() {
+        return quote!();
+    }
+    let TypeIdents{
+        state_type, event_type, event_kind_type, context_type, handler_trait, mock_handler_type, mock_call_type, ..
+    }=type_idents;
+    let asyncness=build_async(options);
+    let context_param=if options.owned() {quote!(_ctx: &mut #context_type,)} else {quote!()};
+    let trans_info_params=quote!(old_state: #state_type, event: &#event_type, new_state: #state_type,);
+    let trans_info_fields=quote!(old_state: #state_type, event: #event_type, new_state: #state_type,);
+    let trans_info_values=quote!(old_state, event: event.clone(), new_state,);
+
+    let mut variants: Vec<TokenStream2>=Vec::new();
+    let mut functions: Vec<TokenStream2>=Vec::new();
+    let mut add_recorded_call=|function: &Ident, params: TokenStream2, fields: TokenStream2, values: TokenStream2| {
+        let variant=format_ident!("{}", to_camel_case(function), span=function.span());
+        let doc=format!("A call of `{}()`", function);
+        if fields.is_empty() {
+            variants.push(quote!(#[doc = #doc] #variant));
+            functions.push(quote!(
+                #asyncness fn #function(&mut self, #context_param #params) {
+                    self.calls.push(#mock_call_type::#variant);
+                }
+            ));
+        } else {
+            variants.push(quote!(#[doc = #doc] #variant{#fields}));
+            functions.push(quote!(
+                #asyncness fn #function(&mut self, #context_param #params) {
+                    self.calls.push(#mock_call_type::#variant{#values});
+                }
+            ));
+        }
+    };
+
+    if let Some(function)=unexpected_event_handler {
+        add_recorded_call(function, quote!(state: #state_type, event: #event_kind_type),
+                          quote!(state: #state_type, event: #event_kind_type), quote!(state, event));
+    }
+    for (handlers, with_trans_info) in [
+        (onentrys, options.entry_handler_with_transition_info),
+        (onexits, options.exit_handler_with_transition_info),
+    ] {
+        for function in handlers {
+            if with_trans_info {
+                add_recorded_call(function, trans_info_params.clone(), trans_info_fields.clone(),
+                                  trans_info_values.clone());
+            } else {
+                add_recorded_call(function, quote!(), quote!(), quote!());
+            }
+        }
+    }
+    for function in actions {
+        let (mut params, mut fields, mut values)=if options.action_handler_with_transition_info {
+            (trans_info_params.clone(), trans_info_fields.clone(), trans_info_values.clone())
+        } else {(quote!(), quote!(), quote!())};
+        if let Some(payload_type)=event_payload_type {
+            params.extend(quote!(payload: &#payload_type));
+            fields.extend(quote!(payload: #payload_type));
+            values.extend(quote!(payload: payload.clone()));
+        }
+        add_recorded_call(function, params, fields, values);
+    }
 
+    let guard_count=guards.len();
+    let guard_params=if options.guard_with_transition_info {
+        quote!(, _state: #state_type, _event: &#event_type)
+    } else {quote!()};
+    let guard_functions=guards.iter().enumerate().map(|(index, guard)| quote!(
+        #asyncness fn #guard(&self #guard_params) -> bool {
+            self.guard_values[#index]
+        }
+    ));
+    let guard_setters=guards.iter().enumerate().map(|(index, guard)| {
+        let setter=format_ident!("set_{}", guard, span=guard.span());
+        let doc=format!("Sets the value returned by `{}()` from now on", guard);
+        quote!(
+            #[doc = #doc]
+            pub fn #setter(&mut self, value: bool) {
+                self.guard_values[#index]=value;
+            }
+        )
+    });
+
+    // Created for scenarios only, the mock handler is needed by their tests, only
+    let mock_cfg=if options.mock_handler {quote!()} else {quote!(#[cfg(test)])};
+    quote!(
+        /// A recorded call of a handler function of the mock handler
+        #mock_cfg
         #[derive(PartialEq,Clone,Debug)]
-        pub #event_definition
+        pub enum #mock_call_type {
+            #(#variants),*
+        }
 
-        pub struct #sm_name<Handler> {
-            handler: std::rc::Rc<std::cell::RefCell<Handler>>,
-            state: std::cell::RefCell<#state_type>,
-            buffered_event: std::cell::RefCell<Option<#event_type>>,
+        /// A handler recording the calls of all entry, exit, action, and unexpected event handlers. All guards
+        /// return `true`, unless set otherwise by their setter, e.g. `set_<guard>()`.
+        #mock_cfg
+        pub struct #mock_handler_type {
+            /// The handler calls, in the order they were made
+            pub calls: Vec<#mock_call_type>,
+            guard_values: [bool; #guard_count],
         }
 
-        impl<Handler> #sm_name<Handler>
-        where Handler: #handler_trait
-        {
-            /// Creates a new instance of this statemachine.
+        #mock_cfg
+        impl #mock_handler_type {
+            /// Creates a new mock handler without any recorded calls
+            pub fn new() -> Self {
+                #mock_handler_type{
+                    calls: Vec::new(),
+                    guard_values: [true; #guard_count],
+                }
+            }
+
+            #(#guard_setters)*
+        }
+
+        #mock_cfg
+        impl Default for #mock_handler_type {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        #mock_cfg
+        impl #handler_trait for #mock_handler_type {
+            #(#functions)*
+            #(#guard_functions)*
+        }
+    )
+}
+
+// Builds the warnings for the findings of the state graph analysis. Since proc macros can't emit warnings on stable
+// Rust, each finding is the use of a deprecated item, located at the offending part of the statemachine definition.
+fn build_analysis_warnings(info: &StatemachineInfo) -> TokenStream2 {
+    let warnings=info.analysis_warnings.iter().map(|(span, message)| {
+        let usage=quote_spanned!(*span=> let _=StatemachineAnalysisWarning;);
+        quote!(
+            const _: () = {
+                #[deprecated(note = #message)]
+                struct StatemachineAnalysisWarning;
+                #usage
+            };
+        )
+    });
+    quote!(#(#warnings)*)
+}
+
+// Builds a test function for each scenario, sending the events of the scenario to a statemachine using the mock
+// handler, and checking the state after each event
+fn build_scenario_tests(info: &StatemachineInfo, type_idents: &TypeIdents) -> TokenStream2 {
+    let StatemachineInfo{sm_name, scenarios, options, ..}=info;
+    let TypeIdents{ state_type, event_type, mock_handler_type, .. }=type_idents;
+    let mutability=if options.owned() {quote!(mut)} else {quote!()};
+
+    let tests=scenarios.iter().map(|ScenarioInfo{name, steps}| {
+        let steps=steps.iter().enumerate().map(|(index, ScenarioStep{event, payload, guard_values, expected_state})| {
+            let payload=payload.as_ref().map(|payload| quote!((#payload)));
+            let guard_values=guard_values.iter().map(|(guard, value)| {
+                let setter=format_ident!("set_{}", guard, span=guard.span());
+                quote!(sm.get_handler_mut().#setter(#value);)
+            });
+            let message=format!("{} scenario {}, step {}: {} -> {}", sm_name, name, index + 1, event, expected_state);
+            quote!(
+                #(#guard_values)*
+                sm.event(#event_type::#event #payload);
+                assert_eq!(sm.get_state(), #state_type::#expected_state, #message);
+            )
+        });
+        quote!(
+            #[cfg(test)]
+            #[test]
+            fn #name() {
+                let #mutability sm=#sm_name::new(#mock_handler_type::new());
+                #(#steps)*
+            }
+        )
+    });
+    quote!(#(#tests)*)
+}
+
+// Builds the random walk for option random_walk: the type generating random event sequences, and the function of the
+// statemachine processing such a sequence while checking an invariant after each step
+fn build_random_walk_tokens(info: &StatemachineInfo, type_idents: &TypeIdents, self_mut: &TokenStream2)
+    -> (TokenStream2, TokenStream2)
+{
+    let StatemachineInfo{event_payload_type, events, options, ..}=info;
+    if !options.random_walk {
+        return (quote!(), quote!());
+    }
+    let TypeIdents{ event_type, event_kind_type, random_walk_type, random_walk_error_type, .. }=type_idents;
+    let asyncness=build_async(options);
+    let dot_await=build_await(options);
+
+    let (payload_param, payload_generic, payload_bound, event_construction)=
+        if let Some(payload_type)=event_payload_type {
+            (
+                quote!(mut payload: P,),
+                quote!(P,),
+                quote!(P: FnMut(#event_kind_type, &mut #random_walk_type) -> #payload_type,),
+                quote!(match kind {
+                    #(#event_kind_type::#events => #event_type::#events(payload(kind, walk)),)*
+                }),
+            )
+        } else {
+            (
+                quote!(),
+                quote!(),
+                quote!(),
+                quote!(match kind {
+                    #(#event_kind_type::#events => #event_type::#events,)*
+                }),
+            )
+        };
+    let payload_doc=if event_payload_type.is_some() {
+        quote!(
+            /// * `payload` - Creates the payload of an event of the given kind, using the random walk for random
+            /// values
+        )
+    } else {quote!()};
+
+    let definition=quote!(
+        /// A generator of random event sequences for `random_walk()`, using a xorshift64* pseudo random number
+        /// generator. The same seed always gives the same sequence.
+        pub struct #random_walk_type {
+            seed: u64,
+            rng_state: u64,
+            respect_transitions: bool,
+        }
+
+        impl #random_walk_type {
+            /// Creates a random walk choosing only events the current state has transitions for
             ///
             /// # Arguments
             ///
-            /// * `handler` - A struct implementing the Handler trait of this statemachine. The statemachine takes
-            /// ownership
-            pub fn new(handler: Handler)->#sm_name<Handler>{
-                let h=std::rc::Rc::new(std::cell::RefCell::new(handler));
-                #sm_name{
-                    handler:h,
-                    state:std::cell::RefCell::new(#state_type::#initial_state),
-                    buffered_event:std::cell::RefCell::new(None),
+            /// * `seed` - The seed of the pseudo random number generator
+            pub fn new(seed: u64) -> Self {
+                #random_walk_type{
+                    seed,
+                    // xorshift gets stuck at 0
+                    rng_state: if seed == 0 {0x9E37_79B9_7F4A_7C15} else {seed},
+                    respect_transitions: true,
+                }
+            }
+
+            /// Sets whether only events the current state has transitions for are chosen (the default), or any
+            /// event, exercising the unexpected event handling, too
+            pub fn respect_transitions(mut self, respect_transitions: bool) -> Self {
+                self.respect_transitions=respect_transitions;
+                self
+            }
+
+            /// Returns the seed the random walk was created with
+            pub fn seed(&self) -> u64 {
+                self.seed
+            }
+
+            /// Returns the next pseudo random number
+            pub fn next_u64(&mut self) -> u64 {
+                self.rng_state^=self.rng_state >> 12;
+                self.rng_state^=self.rng_state << 25;
+                self.rng_state^=self.rng_state >> 27;
+                self.rng_state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+            }
+
+            /// Returns a pseudo random number less than `bound`, which must not be 0
+            pub fn next_below(&mut self, bound: usize) -> usize {
+                (self.next_u64() % bound as u64) as usize
+            }
+        }
+
+        /// The failure of an invariant checked by `random_walk()`, holding the events that led to it
+        #[derive(Debug)]
+        pub struct #random_walk_error_type {
+            /// The seed of the random walk, reproducing the failure
+            pub seed: u64,
+            /// The events processed, the last one of which violated the invariant
+            pub events: Vec<#event_type>,
+            /// The message returned by the invariant
+            pub message: String,
+        }
+
+        impl std::fmt::Display for #random_walk_error_type {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "invariant violated after {} events (seed {}): {}; events: {:?}",
+                       self.events.len(), self.seed, self.message, self.events)
+            }
+        }
+
+        impl std::error::Error for #random_walk_error_type {}
+    );
+
+    let function=quote!(
+        /// Processes a random sequence of events, checking an invariant after each event. The walk ends after
+        /// `steps` events, or when no event can be chosen, i.e. in a final state if transitions are respected.
+        ///
+        /// # Arguments
+        ///
+        /// * `walk` - The random walk choosing the events
+        /// * `steps` - The maximum number of events to be processed
+        #payload_doc
+        /// * `invariant` - Checked after each event, returns an error message if violated
+        ///
+        /// # Errors
+        ///
+        /// Returns the events processed so far if the invariant is violated.
+        pub #asyncness fn random_walk<#payload_generic I>(#self_mut, walk: &mut #random_walk_type, steps: usize,
+                                                            #payload_param mut invariant: I)
+                                                            -> Result<(), #random_walk_error_type>
+        where #payload_bound I: FnMut(&Self) -> Result<(), String>
+        {
+            const ALL_EVENTS: &[#event_kind_type]=&[#(#event_kind_type::#events),*];
+            let mut processed=Vec::new();
+            for _ in 0..steps {
+                let candidates=if walk.respect_transitions {self.accepted_events()} else {ALL_EVENTS};
+                if candidates.is_empty() {
+                    break;
+                }
+                let kind=candidates[walk.next_below(candidates.len())];
+                let ev=#event_construction;
+                processed.push(ev.clone());
+                self.event(ev)#dot_await;
+                if let Err(message)=invariant(self) {
+                    return Err(#random_walk_error_type{seed: walk.seed, events: processed, message});
+                }
+            }
+            Ok(())
+        }
+    );
+
+    (definition, function)
+}
+
+// Builds the coverage report of option coverage: counters of how often each transition was taken and rejected by its
+// guard. Each instance of the statemachine counts on its own, reports of several instances can be merged.
+fn build_coverage_definition(info: &StatemachineInfo, type_idents: &TypeIdents) -> TokenStream2 {
+    if !info.options.coverage {
+        return quote!();
+    }
+    let StatemachineInfo{state_transitions, options, ..}=info;
+    let TypeIdents{ state_type, event_kind_type, coverage_type, transition_coverage_type, .. }=type_idents;
+    let std_crate=if options.no_std {quote!(core)} else {quote!(std)};
+
+    let mut transitions: Vec<TokenStream2>=Vec::new();
+    for StateInfo{ state, transitions: state_transitions, .. } in state_transitions {
+        for TransitionInfo{ event, guard, target_state, .. } in state_transitions {
+            let index=transitions.len();
+            let guard_name=if let Some(gi)=guard {quote!(Some(stringify!(#gi)))} else {quote!(None)};
+            transitions.push(quote!(
+                #transition_coverage_type{
+                    state: #state_type::#state,
+                    event: #event_kind_type::#event,
+                    guard: #guard_name,
+                    new_state: #state_type::#target_state,
+                    taken: self.counters[#index][0],
+                    rejected: self.counters[#index][1],
+                }
+            ));
+        }
+    }
+    let transition_count=transitions.len();
+
+    quote!(
+        /// The coverage of a single transition of the statemachine definition
+        #[derive(PartialEq,Clone,Debug)]
+        pub struct #transition_coverage_type {
+            /// The state the transition starts from
+            pub state: #state_type,
+            /// The kind of the event triggering the transition
+            pub event: #event_kind_type,
+            /// The name of the guard of the transition, if any
+            pub guard: Option<&'static str>,
+            /// The target state of the transition
+            pub new_state: #state_type,
+            /// How often the transition was taken
+            pub taken: usize,
+            /// How often the guard of the transition rejected the event
+            pub rejected: usize,
+        }
+
+        impl #transition_coverage_type {
+            /// Returns true if the transition was taken, and its guard, if any, rejected an event, too
+            pub fn is_covered(&self) -> bool {
+                self.taken > 0 && (self.guard.is_none() || self.rejected > 0)
+            }
+        }
+
+        impl #std_crate::fmt::Display for #transition_coverage_type {
+            fn fmt(&self, f: &mut #std_crate::fmt::Formatter<'_>) -> #std_crate::fmt::Result {
+                write!(f, "{} --{}", self.state, self.event)?;
+                if let Some(guard)=self.guard {
+                    write!(f, "[{}]", guard)?;
+                }
+                write!(f, "--> {}: taken {} times, rejected {} times", self.new_state, self.taken, self.rejected)
+            }
+        }
+
+        /// The transition coverage of a statemachine, or the sum of several statemachines merged by `merge()`
+        #[derive(PartialEq,Clone,Debug)]
+        pub struct #coverage_type {
+            counters: [[usize; 2]; #transition_count],
+        }
+
+        impl #coverage_type {
+            /// Creates a coverage without any transition taken or rejected
+            pub fn new() -> Self {
+                #coverage_type{counters: [[0; 2]; #transition_count]}
+            }
+
+            /// Returns the coverage of all transitions, in order of their definition
+            pub fn transitions(&self) -> [#transition_coverage_type; #transition_count] {
+                [#(#transitions),*]
+            }
+
+            /// Returns the transitions that were never taken, or whose guard never rejected an event
+            pub fn unexercised(&self) -> impl Iterator<Item=#transition_coverage_type> {
+                self.transitions().into_iter().filter(|transition| !transition.is_covered())
+            }
+
+            /// Adds the counters of another coverage, e.g. to sum up the statemachines of several tests
+            pub fn merge(&mut self, other: &Self) {
+                for (counters, other_counters) in self.counters.iter_mut().zip(&other.counters) {
+                    counters[0]+=other_counters[0];
+                    counters[1]+=other_counters[1];
                 }
             }
 
-            /// Returns the handler owned by the statemachine as an Rc<RefCell<>>
-            pub fn get_handler(&self) -> std::rc::Rc<std::cell::RefCell<Handler>> {
-                self.handler.clone()
+            fn count(&mut self, transition: usize, counter: usize) {
+                self.counters[transition][counter]+=1;
             }
+        }
 
-            /// Returns a non-mutable reference to the owned handler
-            pub fn get_handler_ref(&self) -> std::cell::Ref<Handler>{
-                std::cell::RefCell::borrow(&(*self.handler))
+        impl Default for #coverage_type {
+            fn default() -> Self {
+                Self::new()
             }
+        }
+    )
+}
 
-            /// Returns a mutable reference to the owned handler
-            pub fn get_handler_mut(&self) -> std::cell::RefMut<Handler> {
-                std::cell::RefCell::borrow_mut(&(*self.handler))
+// Converts the name of a handler function to CamelCase, e.g. for the variant of its recorded call
+fn to_camel_case(function: &Ident) -> String {
+    function.to_string()
+        .split('_')
+        .map(|word| {
+            let mut chars=word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
             }
+        })
+        .collect()
+}
+
+// Holds the type paths and borrow expressions that differ between the single-threaded (Rc/RefCell) and the
+// thread-safe (Arc/Mutex) variant of the statemachine
+struct CellTokens{
+    shared: TokenStream2,
+    cell: TokenStream2,
+    guard: TokenStream2,
+    guard_mut: TokenStream2,
+    handler_type_name: &'static str,
+    thread_safe: bool,
+}
 
-            /// Returns the current state the statemachine is in
-            pub fn get_state(&self)-> #state_type {
-                (*(std::cell::RefCell::borrow(&self.state))).clone()
+impl CellTokens {
+    fn new(options: &Options) -> Self {
+        if options.thread_safe {
+            CellTokens{
+                shared: quote!(std::sync::Arc),
+                cell: quote!(std::sync::Mutex),
+                guard: quote!(std::sync::MutexGuard),
+                guard_mut: quote!(std::sync::MutexGuard),
+                handler_type_name: "Arc<Mutex<>>",
+                thread_safe: true,
             }
+        } else {
+            CellTokens{
+                shared: quote!(std::rc::Rc),
+                cell: quote!(std::cell::RefCell),
+                guard: quote!(std::cell::Ref),
+                guard_mut: quote!(std::cell::RefMut),
+                handler_type_name: "Rc<RefCell<>>",
+                thread_safe: false,
+            }
+        }
+    }
 
-            /// Processes an event. This is the main function of this statemachine, implementing the actual behavior.
-            ///
-            /// # Arguments
-            ///
-            /// * `ev` - The event to be processed. The statemachine takes ownership. Subsequent calls to handler
-            /// functions will use references, only, to avoid cloning an potentially large event payload.
-            ///
-            /// If any handler calls "event_from_handler()" in the processing of `ev`, the new event will be processed
-            /// directly after `ev`.
-            pub fn event(&self,ev: #event_type) {
-                let state=self.get_state();
-                #[allow(unreachable_patterns)]
-                match state {
-                    #(#state_tokens),*
-                }
-                let ev=std::cell::RefCell::borrow_mut(&self.buffered_event).take();
-                if ev.is_some() {
-                    self.event(ev.unwrap());
+    // Builds the expression borrowing the content of a cell non-mutably
+    fn borrow(&self, cell: TokenStream2) -> TokenStream2 {
+        if self.thread_safe {
+            // A panicking handler must not render the statemachine unusable, just like with RefCell
+            quote!((#cell).lock().unwrap_or_else(std::sync::PoisonError::into_inner))
+        } else {
+            quote!(std::cell::RefCell::borrow(&(#cell)))
+        }
+    }
+
+    // Builds the field definitions and field initializations marking the dispatch of an event
+    fn dispatch_fields(&self) -> (TokenStream2, TokenStream2) {
+        if self.thread_safe {
+            (
+                quote!(
+                    dispatch: std::sync::Mutex<()>,
+                    dispatching_thread: std::sync::Mutex<Option<std::thread::ThreadId>>,
+                ),
+                quote!(
+                    dispatch: std::sync::Mutex::new(()),
+                    dispatching_thread: std::sync::Mutex::new(None),
+                ),
+            )
+        } else {
+            (
+                quote!(dispatching: std::cell::Cell<bool>,),
+                quote!(dispatching: std::cell::Cell::new(false),),
+            )
+        }
+    }
+
+    // Builds the statement running `on_reentrant` if the thread-safe statemachine is called from a handler, i.e. from
+    // the dispatching thread while the handler is locked. Locking the handler again would deadlock, while the
+    // single-threaded variant relies on RefCell detecting conflicting borrows.
+    fn reentrancy_check(&self, on_reentrant: TokenStream2) -> TokenStream2 {
+        if self.thread_safe {
+            quote!(
+                if *self.dispatching_thread.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+                    == Some(std::thread::current().id()) {
+                    #on_reentrant
                 }
-            }
+            )
+        } else {
+            quote!()
+        }
+    }
 
-            /// Enqueues an event for processing. This function is intended to be used from handlers, only. Only one
-            /// event can be enqueued at a time, subsequent calls will overwrite any previous event.
-            ///
-            /// # Arguments
-            ///
-            /// * `ev` - The event to be enqueued. The statemachine takes ownership.
-            pub fn event_from_handler(&self,ev: #event_type) {
-                *std::cell::RefCell::borrow_mut(&self.buffered_event)=Some(ev);
-            }
+    // Builds the statements starting a dispatch in event(), start(), or stop(). A call while an event is dispatched,
+    // i.e. from a handler, runs `on_reentrant`, which has to return. For the thread-safe variant, this applies to
+    // calls from the dispatching thread, only, concurrent calls from other threads are serialized by a lock. The
+    // dispatch marker is reset by a guard, even if a handler panics.
+    fn dispatch_start(&self, on_reentrant: TokenStream2) -> TokenStream2 {
+        if self.thread_safe {
+            quote!(
+                struct DispatchGuard<'a>(&'a std::sync::Mutex<Option<std::thread::ThreadId>>);
+                impl Drop for DispatchGuard<'_> {
+                    fn drop(&mut self) {
+                        *self.0.lock().unwrap_or_else(std::sync::PoisonError::into_inner)=None;
+                    }
+                }
 
-            fn call_on_entry(&self #entry_trans_info_tokens)
-            {
-                #[allow(unreachable_patterns)]
-                match *std::cell::RefCell::borrow(&self.state) {
-                    #(#state_on_entry_tokens),*
-                    _=>(),
+                let current_thread=std::thread::current().id();
+                if *self.dispatching_thread.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+                    == Some(current_thread) {
+                    #on_reentrant
+                }
+                let _dispatch=self.dispatch.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+                *self.dispatching_thread.lock().unwrap_or_else(std::sync::PoisonError::into_inner)=
+                    Some(current_thread);
+                let _dispatch_guard=DispatchGuard(&self.dispatching_thread);
+            )
+        } else {
+            quote!(
+                struct DispatchGuard<'a>(&'a std::cell::Cell<bool>);
+                impl Drop for DispatchGuard<'_> {
+                    fn drop(&mut self) {
+                        self.0.set(false);
+                    }
                 }
-            }
 
-            fn call_on_exit(&self #exit_trans_info_tokens)
-            {
-               #[allow(unreachable_patterns)]
-               match *std::cell::RefCell::borrow(&self.state) {
-                    #(#state_on_exit_tokens),*
-                    _=>(),
+                if self.dispatching.replace(true) {
+                    #on_reentrant
                 }
-            }
+                let _dispatch_guard=DispatchGuard(&self.dispatching);
+            )
         }
+    }
 
-        /// The trait to be implemented by structs to actually do work when called by the statemachine.
-        pub trait #handler_trait {
-            #unexpected_event_handler_token
-            #(#onentrys)*
-            #(#onexits)*
-            #(#guards)*
-            #(#actions)*
+    // Builds the expression consuming a cell, returning its content
+    fn unwrap_cell(&self, cell: TokenStream2) -> TokenStream2 {
+        if self.thread_safe {
+            quote!((#cell).into_inner().unwrap_or_else(std::sync::PoisonError::into_inner))
+        } else {
+            quote!((#cell).into_inner())
         }
-    ).into();
-    output
+    }
+
+    // Builds the expression borrowing the content of a cell mutably
+    fn borrow_mut(&self, cell: TokenStream2) -> TokenStream2 {
+        if self.thread_safe {
+            self.borrow(cell)
+        } else {
+            quote!(std::cell::RefCell::borrow_mut(&(#cell)))
+        }
+    }
 }
 
 struct StateTokens{
@@ -1019,7 +4192,7 @@ struct StateTokens{
 // state_tokens, state_on_entry_tokens, state_on_exit_tokens
 fn build_state_dependent_tokens(
     info: &StatemachineInfo, type_idents: &TypeIdents, options: &Options,
-    state_transitions: &Vec<StateInfo>)
+    state_transitions: &[StateInfo])
     -> StateTokens
 {
     let TypeIdents{state_type,..}=type_idents;
@@ -1041,23 +4214,25 @@ fn build_state_dependent_tokens(
     } else { quote!() };
 
 
-    let mut sti = state_transitions.iter();
-
-    while let Some(st) = sti.next() {
+    for st in state_transitions {
         state_tokens.push(
-            build_state_match(&info, &type_idents, st)
+            build_state_match(info, type_idents, st)
         );
         let StateInfo { state, onentry, onexit, .. } = st;
         if let Some(oei) = onentry {
             state_on_entry_tokens.push(
-                quote!(#state_type::#state => {(*self.get_handler_mut()).#oei(
-                            #entry_trans_info_tokens);})
+                {
+                    let call=build_handler_mut_call(options, oei, entry_trans_info_tokens.clone());
+                    quote!(#state_type::#state => {#call;})
+                }
             );
         }
         if let Some(oei) = onexit {
             state_on_exit_tokens.push(
-                quote!(#state_type::#state => {(*self.get_handler_mut()).#oei(
-                            #exit_trans_info_tokens);})
+                {
+                    let call=build_handler_mut_call(options, oei, exit_trans_info_tokens.clone());
+                    quote!(#state_type::#state => {#call;})
+                }
             );
         }
     }
@@ -1066,9 +4241,59 @@ fn build_state_dependent_tokens(
 
 
 
+struct EventQueryTokens{
+    accepted_events_tokens: Vec<TokenStream2>,
+    can_handle_tokens: Vec<TokenStream2>,
+}
+
+// Builds the per-state match arms of accepted_events() and can_handle()
+fn build_event_query_tokens(
+    type_idents: &TypeIdents, options: &Options,
+    state_transitions: &[StateInfo])
+    -> EventQueryTokens
+{
+    let TypeIdents{ state_type, event_type, event_kind_type, .. }=type_idents;
+
+    let mut accepted_events_tokens: Vec<TokenStream2> = Vec::new();
+    let mut can_handle_tokens: Vec<TokenStream2> = Vec::new();
+
+    let event_payload_tokens = if options.has_payload {quote!((_))} else {quote!()};
+
+    for StateInfo{ state, transitions, .. } in state_transitions {
+        let mut accepted_events: Vec<&Ident> = Vec::new();
+        let mut trans_tokens: Vec<TokenStream2> = Vec::new();
+        for TransitionInfo{ event, guard, .. } in transitions {
+            if !accepted_events.contains(&event) {
+                accepted_events.push(event);
+            }
+            let guard_tokens = if let Some(gi) = guard {
+                let transinfo = if options.guard_with_transition_info {
+                    quote!(#state_type::#state,ev)
+                } else {quote!()};
+                let call = build_guard_call(options, gi, transinfo);
+                quote!(if #call)
+            } else {quote!()};
+            trans_tokens.push(quote!(#event_type::#event #event_payload_tokens #guard_tokens => true));
+        }
+
+        accepted_events_tokens.push(quote!(#state_type::#state => &[#(#event_kind_type::#accepted_events),*]));
+        can_handle_tokens.push(quote!(
+            #state_type::#state => {
+                #[allow(unreachable_patterns)]
+                match ev {
+                    #(#trans_tokens,)*
+                    _ => false,
+                }
+            }
+        ));
+    }
+    EventQueryTokens{accepted_events_tokens, can_handle_tokens}
+}
+
+
 // Build the event enum definition, optionally with payload as enum value content
 fn build_event_enum_definition(
-    type_idents: &TypeIdents, event_payload_type: &Option<Ident>, events: &Vec<Ident>)
+    type_idents: &TypeIdents, event_payload_type: &Option<Ident>, events: &[Ident])
     -> TokenStream2
 {
     let TypeIdents{ event_type,..}=type_idents;
@@ -1091,55 +4316,234 @@ fn build_event_enum_definition(
     )
 }
 
+// Build the payload-free event kind enum and the conversion from events to their kind
+fn build_event_kind_definition(
+    type_idents: &TypeIdents, options: &Options, events: &[Ident])
+    -> TokenStream2
+{
+    let TypeIdents{ event_type, event_kind_type, .. }=type_idents;
+
+    let event_payload_tokens = if options.has_payload {quote!((_))} else {quote!()};
+    let serde_derive=build_serde_derive(options);
+
+    quote!(
+        /// The kind of an event, i.e. the event without its payload
+        #[derive(PartialEq,Eq,Clone,Copy,Debug,Hash)]
+        #serde_derive
+        #[allow(clippy::enum_variant_names)]
+        pub enum #event_kind_type {
+            #(#events),*
+        }
+
+        impl #event_type {
+            /// Returns the kind of this event, i.e. the event without its payload
+            pub fn kind(&self) -> #event_kind_type {
+                match *self {
+                    #(#event_type::#events #event_payload_tokens => #event_kind_type::#events,)*
+                }
+            }
+        }
+
+        impl From<&#event_type> for #event_kind_type {
+            fn from(ev: &#event_type) -> Self {
+                ev.kind()
+            }
+        }
+    )
+}
+
+// Builds the handler-free description of the transitions, as table of all transitions, and as function returning the
+// possible targets of an event in a state
+fn build_transition_table(type_idents: &TypeIdents, state_transitions: &[StateInfo]) -> TokenStream2 {
+    let TypeIdents{ state_type, event_kind_type, .. }=type_idents;
+    let build_guard_name=|guard: &Option<Ident>| {
+        if let Some(gi)=guard {quote!(Some(stringify!(#gi)))} else {quote!(None)}
+    };
+
+    let mut table_rows: Vec<TokenStream2>=Vec::new();
+    let mut next_states_arms: Vec<TokenStream2>=Vec::new();
+    for StateInfo{ state, transitions, .. } in state_transitions {
+        let mut handled_events: Vec<&Ident>=Vec::new();
+        for TransitionInfo{ event, guard, target_state, .. } in transitions {
+            let guard_name=build_guard_name(guard);
+            table_rows.push(quote!((#state_type::#state, #event_kind_type::#event, #guard_name,
+                                    #state_type::#target_state)));
+            if handled_events.contains(&event) {
+                continue;
+            }
+            handled_events.push(event);
+            let targets=transitions.iter().filter(|t| t.event == *event).map(|t| {
+                let guard_name=build_guard_name(&t.guard);
+                let target_state=&t.target_state;
+                quote!((#guard_name, #state_type::#target_state))
+            });
+            next_states_arms.push(quote!(
+                (#state_type::#state, #event_kind_type::#event) => &[#(#targets),*],
+            ));
+        }
+    }
+
+    quote!(
+        /// All transitions of the statemachine definition, in order of their definition: the state, the kind of the
+        /// triggering event, the name of the guard, if any, and the target state
+        pub const TRANSITIONS: &'static [(#state_type, #event_kind_type, Option<&'static str>, #state_type)]=&[
+            #(#table_rows),*
+        ];
+
+        /// Returns the possible targets of an event in this state, in order of their definition, each with the name
+        /// of its guard, if any. No handler is involved, guards are not evaluated. An empty list means the event is
+        /// unexpected in this state.
+        ///
+        /// # Arguments
+        ///
+        /// * `event` - The kind of the event
+        pub fn next_states(&self, event: #event_kind_type) -> &'static [(Option<&'static str>, #state_type)] {
+            #[allow(unreachable_patterns)]
+            match (self, event) {
+                #(#next_states_arms)*
+                _ => &[],
+            }
+        }
+    )
+}
+
+// Build as_str(), Display, and FromStr for a payload-free enum, using the names given in the statemachine definition
+fn build_name_conversions(
+    type_ident: &Ident, parse_error_type: &Ident, std_crate: &TokenStream2, values: &[Ident])
+    -> TokenStream2
+{
+    let names: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+
+    quote!(
+        impl #type_ident {
+            /// Returns the name of this value as given in the statemachine definition
+            pub fn as_str(&self) -> &'static str {
+                match *self {
+                    #(#type_ident::#values => #names,)*
+                }
+            }
+        }
+
+        impl #std_crate::fmt::Display for #type_ident {
+            fn fmt(&self, f: &mut #std_crate::fmt::Formatter<'_>) -> #std_crate::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl #std_crate::str::FromStr for #type_ident {
+            type Err = #parse_error_type;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    #(#names => Ok(#type_ident::#values),)*
+                    _ => Err(#parse_error_type),
+                }
+            }
+        }
+    )
+}
+
 // Build the main state match structure, filling it with transition matches per state
 fn build_state_match(info: &StatemachineInfo,type_idents: &TypeIdents, state_info: &StateInfo)
     ->TokenStream2
 {
     let StatemachineInfo{unexpected_event_handler,options,..}=info;
-    let TypeIdents{ state_type: state_type_ident,..}=type_idents;
+    let TypeIdents{ state_type: state_type_ident, event_type, outcome_type, .. }=type_idents;
     let StateInfo{ state: state_ident,transitions,..}=state_info;
 
     let mut trans_tokens:Vec<TokenStream2>=Vec::new();
 
-    let mut transit=transitions.iter();
-    while let Some(trans)=transit.next() {
+    // The transitions are numbered in order of their definition, for the coverage counters
+    let first_transition_index: usize=info.state_transitions.iter()
+        .take_while(|st| st.state != *state_ident)
+        .map(|st| st.transitions.len())
+        .sum();
+    for (index, trans) in transitions.iter().enumerate() {
         trans_tokens.push(
-            build_transition_match_line(type_idents,options,state_ident,trans)
+            build_transition_match_line(&info.sm_name,type_idents,options,info.history_capacity.is_some(),state_ident,
+                                        trans, first_transition_index + index)
         )
     }
 
-    let mut call_unexpected_handler= quote!(());
+    let mut call_unexpected_handler= quote!();
+    let mut unexpected_outcome=quote!(#outcome_type::Ignored{state});
     if info.unexpected_event_handler.is_some() {
         let unexpected_event_handler_ident=unexpected_event_handler.as_ref().unwrap();
-        call_unexpected_handler=quote!({self.get_handler_mut().#unexpected_event_handler_ident(state,&ev);});
+        let call=build_handler_mut_call(options, unexpected_event_handler_ident, quote!(state.clone(),ev.kind()));
+        call_unexpected_handler=quote!(#call;);
+        unexpected_outcome=quote!(#outcome_type::Unexpected{state});
+    }
+
+    let call_on_event_ignored=build_observer_call(options, quote!(on_event_ignored(state.clone(),&ev)));
+
+    // Events whose transitions are all guarded are blocked if no guard accepts them
+    let event_payload_tokens=if options.has_payload {quote!((_))} else {quote!()};
+    let mut blocked_events: Vec<&Ident>=Vec::new();
+    for TransitionInfo{ event, .. } in transitions {
+        let all_guarded=transitions.iter().filter(|t| t.event==*event).all(|t| t.guard.is_some());
+        if all_guarded && !blocked_events.contains(&event) {
+            blocked_events.push(event);
+        }
     }
 
     quote!(
             #state_type_ident::#state_ident => {match &ev {
                 #(#trans_tokens),*
-                _ => #call_unexpected_handler,
-            };}
+                #(#event_type::#blocked_events #event_payload_tokens => {
+                    #call_unexpected_handler
+                    #call_on_event_ignored
+                    #outcome_type::Blocked{state}
+                },)*
+                _ => {
+                    #call_unexpected_handler
+                    #call_on_event_ignored
+                    #unexpected_outcome
+                },
+            }}
         )
 }
 
 
 // Builds one line of event matching for a given state, incuding calling guards, exit/entry and action handlers
 fn build_transition_match_line(
+    sm_name: &Ident,
     type_idents:&TypeIdents,
     options: &Options,
+    history: bool,
     state: &Ident,
-    trans:&TransitionInfo
+    trans:&TransitionInfo,
+    transition_index: usize
 ) ->TokenStream2 {
-    let TypeIdents{ state_type, event_type}=type_idents;
+    let TypeIdents{ state_type, event_type, outcome_type, .. }=type_idents;
     let Options{has_payload,guard_with_transition_info,..}=options;
     let TransitionInfo{ event, guard, action, target_state }=trans;
+    let dot_await=build_await(options);
 
     let guard_tokens=
         if let Some(gi)= guard {
             let transinfo=if *guard_with_transition_info {
                 quote!(#state_type::#state,&ev)
             } else {quote!()};
-            quote!(if (*self.get_handler_ref()).#gi(#transinfo))
+            let call=build_guard_call(options, gi, transinfo);
+            let log_guard=build_log(sm_name, options, quote!(trace), "guard evaluated", &[
+                ("state", quote!(#state_type::#state)),
+                ("guard", quote!(stringify!(#gi))),
+                ("accepted", quote!(accepted)),
+            ]);
+            let count_rejection=build_coverage_count(options, transition_index, 1);
+            let count_rejection=if count_rejection.is_empty() {quote!()} else {
+                quote!(if !accepted {#count_rejection})
+            };
+            if log_guard.is_empty() && count_rejection.is_empty() {
+                quote!(if #call)
+            } else {
+                quote!(if {
+                    let accepted=#call;
+                    #log_guard
+                    #count_rejection
+                    accepted
+                })
+            }
         } else {quote!()};
 
     let trans_info_tokens=if options.action_handler_with_transition_info {
@@ -1164,31 +4568,165 @@ fn build_transition_match_line(
     let action_payload_tokens =if *has_payload {quote!(&pay,)} else {quote!()};
 
     let action_tokens=if let Some(ai)= action {
-        quote!((*self.get_handler_mut()).#ai(
+        let call=build_handler_mut_call(options, ai, quote!(
                 #trans_info_tokens
-                #action_payload_tokens);
+                #action_payload_tokens));
+        let log_action=build_log(sm_name, options, quote!(trace), "calling action", &[("action", quote!(stringify!(#ai)))]);
+        quote!(
+            #log_action
+            #call;
         )
     }else{quote!()};
 
+    let record_history=if history {
+        let guard_name=if let Some(gi)=guard {quote!(Some(stringify!(#gi)))} else {quote!(None)};
+        quote!(self.record_history(#state_type::#state, ev.kind(), #guard_name, #state_type::#target_state);)
+    } else {quote!()};
+
+    let log_state_change=build_log(sm_name, options, quote!(debug), "state changed", &[
+        ("old_state", quote!(#state_type::#state)),
+        ("new_state", quote!(#state_type::#target_state)),
+    ]);
+
     let event_payload_tokens =if *has_payload {quote!((pay))} else {quote!()};
 
+    let call_on_transition=build_observer_call(options, quote!(
+        on_transition(#state_type::#state, &ev, #state_type::#target_state)));
+
+    let count_transition=build_coverage_count(options, transition_index, 0);
+
     quote!(
         #event_type::#event #event_payload_tokens #guard_tokens => {
-            self.call_on_exit(#exit_trans_info_tokens);
+            #count_transition
+            self.call_on_exit(#exit_trans_info_tokens)#dot_await;
             #action_tokens
-            *(std::cell::RefCell::borrow_mut(&self.state))=#state_type::#target_state;
-            self.call_on_entry(#entry_trans_info_tokens);
+            self.set_state(#state_type::#target_state);
+            #record_history
+            #log_state_change
+            self.call_on_entry(#entry_trans_info_tokens)#dot_await;
+            #call_on_transition
+            #outcome_type::Transition{old_state: #state_type::#state, new_state: #state_type::#target_state}
         }
     )
 }
 
 
+// Builds the increment of a coverage counter of a transition, if option coverage is given. Counter 0 counts the
+// transition being taken, counter 1 its guard rejecting the event.
+fn build_coverage_count(options: &Options, transition_index: usize, counter: usize) -> TokenStream2 {
+    if options.coverage {
+        quote!(self.count_coverage(#transition_index, #counter);)
+    } else {
+        quote!()
+    }
+}
+
+// Builds the call of a handler function that may change the handler. Statemachines owning their handler pass the
+// context as first argument, so handler functions can post events.
+fn build_handler_mut_call(options: &Options, function: &Ident, args: TokenStream2) -> TokenStream2 {
+    let dot_await=build_await(options);
+    if options.owned() {
+        quote!(self.handler.#function(&mut self.context, #args)#dot_await)
+    } else {
+        quote!((*self.handler_mut()).#function(#args))
+    }
+}
+
+// Builds the serde derives of the data types, if option serde is given
+fn build_serde_derive(options: &Options) -> TokenStream2 {
+    if options.serde {
+        quote!(#[derive(::serde::Serialize, ::serde::Deserialize)])
+    } else {
+        quote!()
+    }
+}
+
+// Builds the statement emitting a log record via the log crate and/or an event via the tracing crate, depending on
+// the options log and tracing. The fields are formatted by Display. Returns no tokens if both options are off.
+fn build_log(
+    sm_name: &Ident, options: &Options, level: TokenStream2, message: &str, fields: &[(&str, TokenStream2)]
+) -> TokenStream2 {
+    let mut tokens=quote!();
+    let values: Vec<&TokenStream2>=fields.iter().map(|(_, value)| value).collect();
+    if options.log {
+        let format: String=fields.iter().map(|(name, _)| format!(", {}={{}}", name)).collect();
+        let format=format!("{}: {}{}", sm_name, message, format);
+        tokens.extend(quote!(::log::#level!(#format #(, #values)*);));
+    }
+    if options.tracing {
+        let names: Vec<Ident>=fields.iter().map(|(name, _)| format_ident!("{}", name)).collect();
+        let sm_name_str=sm_name.to_string();
+        tokens.extend(quote!(::tracing::#level!(statemachine=#sm_name_str #(, #names=%#values)*, #message);));
+    }
+    tokens
+}
+
+// Wraps the processing of an event in a tracing span, if option tracing is given. Async statemachines instrument
+// the processing instead of entering the span, as the span must not stay entered while the future is suspended.
+fn build_dispatch_span(sm_name: &Ident, options: &Options, dispatch: TokenStream2) -> TokenStream2 {
+    if !options.tracing {
+        return dispatch;
+    }
+    let sm_name_str=sm_name.to_string();
+    let span=quote!(::tracing::debug_span!("event", statemachine=#sm_name_str, state=%state, event=%ev.kind()));
+    if options.async_handlers {
+        quote!(
+            let span=#span;
+            ::tracing::Instrument::instrument(async {#dispatch}, span).await
+        )
+    } else {
+        quote!(
+            let _span=#span.entered();
+            #dispatch
+        )
+    }
+}
+
+// Builds the call of an observer function of the handler. Observers neither receive a context nor are async.
+fn build_observer_call(options: &Options, call: TokenStream2) -> TokenStream2 {
+    if options.owned() {
+        quote!(self.handler.#call;)
+    } else {
+        quote!((*self.handler_mut()).#call;)
+    }
+}
+
+// Builds the call of a guard function
+fn build_guard_call(options: &Options, function: &Ident, args: TokenStream2) -> TokenStream2 {
+    let dot_await=build_await(options);
+    if options.owned() {
+        quote!(self.handler.#function(#args)#dot_await)
+    } else {
+        quote!((*self.handler_ref()).#function(#args))
+    }
+}
+
+// Builds the `async` keyword for functions calling handlers, if handlers are async
+fn build_async(options: &Options) -> TokenStream2 {
+    if options.async_handlers {quote!(async)} else {quote!()}
+}
+
+// Builds the `.await` for calls of functions calling handlers, if handlers are async
+fn build_await(options: &Options) -> TokenStream2 {
+    if options.async_handlers {quote!(.await)} else {quote!()}
+}
+
+// Builds the context parameter of handler functions that may change the handler, for statemachines owning their
+// handler
+fn build_context_param(type_idents: &TypeIdents, options: &Options) -> TokenStream2 {
+    let TypeIdents{ context_type, .. }=type_idents;
+    if options.owned() {
+        quote!(, ctx: &mut #context_type)
+    } else {quote!()}
+}
+
+
 // Builds the list of guards to be used in the trait definition
 fn build_guards_list(
     type_idents: &TypeIdents, options: &Options,
-    guards:&Vec<Ident>
+    guards:&[Ident]
 ) -> Vec<TokenStream2> {
-    let TypeIdents{ state_type, event_type }=type_idents;
+    let TypeIdents{ state_type, event_type, .. }=type_idents;
 
     let mut gv:Vec<TokenStream2>=Vec::new();
 
@@ -1196,9 +4734,10 @@ fn build_guards_list(
         quote!(,state: #state_type,event:&#event_type)
     } else { quote!() };
 
+    let asyncness=build_async(options);
     guards.iter().for_each(|gi|{
         gv.push(
-            quote!(fn #gi(&self #transitions)->bool;)
+            quote!(#asyncness fn #gi(&self #transitions)->bool;)
         );
     });
 
@@ -1211,11 +4750,13 @@ fn build_actions_list(
     type_idents: &TypeIdents,
     event_payload_type: &Option<Ident>,
     options: &Options,
-    actions:&Vec<Ident>
+    actions:&[Ident]
 ) -> Vec<TokenStream2> {
-    let TypeIdents{ state_type, event_type }=type_idents;
+    let TypeIdents{ state_type, event_type, .. }=type_idents;
 
     let payload_tokens=if let Some(plt)= event_payload_type {quote!(payload: &#plt)} else {quote!()};
+    let context_param=build_context_param(type_idents, options);
+    let asyncness=build_async(options);
     let mut av:Vec<TokenStream2>=Vec::new();
 
     let trans_info_tokens=if options.action_handler_with_transition_info {
@@ -1226,7 +4767,7 @@ fn build_actions_list(
 
     actions.iter().for_each(|ai|{
         av.push(
-            quote!(fn #ai(&mut self,
+            quote!(#asyncness fn #ai(&mut self #context_param,
                                 #trans_info_tokens
                                 #payload_tokens
                                 );)
@@ -1239,9 +4780,9 @@ fn build_actions_list(
 // Builds the list of entry handlers to be used in the trait definition
 fn build_onentrys_list(
     type_idents: &TypeIdents, options: &Options,
-    onentrys:&Vec<Ident>
+    onentrys:&[Ident]
 ) -> Vec<TokenStream2> {
-    let TypeIdents{ state_type, event_type }=type_idents;
+    let TypeIdents{ state_type, event_type, .. }=type_idents;
     let Options{entry_handler_with_transition_info,..}=options;
 
     let transinfo=if *entry_handler_with_transition_info {
@@ -1251,10 +4792,12 @@ fn build_onentrys_list(
                 new_state: #state_type)
     } else {quote!()};
 
+    let context_param=build_context_param(type_idents, options);
+    let asyncness=build_async(options);
     let mut oev:Vec<TokenStream2>=Vec::new();
     onentrys.iter().for_each(|oi|{
         oev.push(
-            quote!(fn #oi(&mut self #transinfo);)
+            quote!(#asyncness fn #oi(&mut self #context_param #transinfo);)
         );
     });
     oev
@@ -1264,9 +4807,9 @@ fn build_onentrys_list(
 // Builds the list of exit handlers to be used in the trait definition
 fn build_onexits_list(
     type_idents: &TypeIdents, options: &Options,
-    onexits:&Vec<Ident>
+    onexits:&[Ident]
 ) -> Vec<TokenStream2> {
-    let TypeIdents{ state_type, event_type }=type_idents;
+    let TypeIdents{ state_type, event_type, .. }=type_idents;
     let Options{exit_handler_with_transition_info,..}=options;
 
     let transinfo=if *exit_handler_with_transition_info {
@@ -1277,10 +4820,12 @@ fn build_onexits_list(
     } else {quote!()};
 
 
+    let context_param=build_context_param(type_idents, options);
+    let asyncness=build_async(options);
     let mut oev:Vec<TokenStream2>=Vec::new();
     onexits.iter().for_each(|oi|{
         oev.push(
-            quote!(fn #oi(&mut self #transinfo);)
+            quote!(#asyncness fn #oi(&mut self #context_param #transinfo);)
         );
     });
     oev
@@ -1300,8 +4845,12 @@ mod kw {
     custom_keyword!(InitialState);
     custom_keyword!(EventPayload);
     custom_keyword!(UnexpectedHandler);
+    custom_keyword!(EventQueueCapacity);
+    custom_keyword!(MaxSteps);
+    custom_keyword!(History);
     custom_keyword!(OnEntry);
     custom_keyword!(OnExit);
+    custom_keyword!(Scenario);
 }
 
 
@@ -1312,6 +4861,9 @@ struct StatemachineInfo {
     initial_state: Ident,
     event_payload_type: Option<Ident>,
     unexpected_event_handler: Option<Ident>,
+    event_queue_capacity: Option<usize>,
+    max_steps: Option<usize>,
+    history_capacity: Option<usize>,
     states:Vec<Ident>,
     events:Vec<Ident>,
     onentrys:Vec<Ident>,
@@ -1319,6 +4871,9 @@ struct StatemachineInfo {
     guards:Vec<Ident>,
     actions:Vec<Ident>,
     state_transitions: Vec<StateInfo>,
+    scenarios: Vec<ScenarioInfo>,
+    // Findings of the analysis of the state graph, emitted as warnings
+    analysis_warnings: Vec<(Span, String)>,
 
     options: Options,
 }
@@ -1328,6 +4883,18 @@ struct StatemachineInfo {
 #[derive(Debug)]
 struct Options {
     has_payload: bool,
+    thread_safe: bool,
+    no_std: bool,
+    direct_ownership: bool,
+    async_handlers: bool,
+    legacy_start: bool,
+    mock_handler: bool,
+    random_walk: bool,
+    strict: bool,
+    log: bool,
+    tracing: bool,
+    serde: bool,
+    coverage: bool,
     action_handler_with_transition_info: bool,
     entry_handler_with_transition_info: bool,
     exit_handler_with_transition_info: bool,
@@ -1335,6 +4902,16 @@ struct Options {
 }
 
 
+impl Options {
+    // Returns true if the statemachine owns handler and state directly, instead of sharing them via Rc/RefCell or
+    // Arc/Mutex
+    fn owned(&self) -> bool {
+        self.no_std || self.direct_ownership || self.async_handlers
+    }
+
+}
+
+
 // Holds one state: state name, optional entry handler, optional exit handler, list of transitions (may be empty)
 #[derive(Debug)]
 struct StateInfo {
@@ -1355,6 +4932,25 @@ struct TransitionInfo {
 }
 
 
+// Holds one scenario: test name, and the events to be sent, each with the state expected afterwards
+#[derive(Debug)]
+struct ScenarioInfo {
+    name: Ident,
+    steps: Vec<ScenarioStep>,
+}
+
+
+// Holds one step of a scenario: event, its payload if the statemachine has an event payload, the guard values set
+// before sending the event, expected state
+#[derive(Debug)]
+struct ScenarioStep {
+    event: Ident,
+    payload: Option<Expr>,
+    guard_values: Vec<(Ident, LitBool)>,
+    expected_state: Ident,
+}
+
+
 impl Parse for StatemachineInfo {
 
     // parses the statemachine definition
@@ -1362,6 +4958,18 @@ impl Parse for StatemachineInfo {
 
         let Options{
             has_payload:_has_payload,
+            thread_safe,
+            no_std,
+            direct_ownership,
+            async_handlers,
+            legacy_start,
+            mock_handler,
+            random_walk,
+            strict,
+            log,
+            tracing,
+            serde,
+            coverage,
             action_handler_with_transition_info,
             entry_handler_with_transition_info,
             exit_handler_with_transition_info,
@@ -1389,6 +4997,51 @@ impl Parse for StatemachineInfo {
         }
 
 
+        let mut event_queue_capacity=None;
+        if input.peek(kw::EventQueueCapacity) {
+            input.parse::<kw::EventQueueCapacity>()?;
+            let capacity_lit: LitInt = input.parse()?;
+            let capacity: usize = capacity_lit.base10_parse()?;
+            if capacity == 0 {
+                return Err(syn::parse::Error::new(capacity_lit.span(), "Event queue capacity must not be 0"));
+            }
+            if no_std && serde && capacity > MAX_SERDE_ARRAY_SIZE {
+                return Err(syn::parse::Error::new(capacity_lit.span(), format!(
+                    "Event queue capacity must not exceed {} with options no_std and serde", MAX_SERDE_ARRAY_SIZE)));
+            }
+            event_queue_capacity = Some(capacity);
+        }
+
+
+        let mut max_steps=None;
+        if input.peek(kw::MaxSteps) {
+            input.parse::<kw::MaxSteps>()?;
+            let max_steps_lit: LitInt = input.parse()?;
+            let steps: usize = max_steps_lit.base10_parse()?;
+            if steps == 0 {
+                return Err(syn::parse::Error::new(max_steps_lit.span(), "Maximum step count must not be 0"));
+            }
+            max_steps = Some(steps);
+        }
+
+
+        let mut history_capacity=None;
+        // A state may be named History, too
+        if input.peek(kw::History) && input.peek2(LitInt) {
+            input.parse::<kw::History>()?;
+            let capacity_lit: LitInt = input.parse()?;
+            let capacity: usize = capacity_lit.base10_parse()?;
+            if capacity == 0 {
+                return Err(syn::parse::Error::new(capacity_lit.span(), "History size must not be 0"));
+            }
+            if no_std && serde && capacity > MAX_SERDE_ARRAY_SIZE {
+                return Err(syn::parse::Error::new(capacity_lit.span(), format!(
+                    "History size must not exceed {} with options no_std and serde", MAX_SERDE_ARRAY_SIZE)));
+            }
+            history_capacity = Some(capacity);
+        }
+
+
         let mut states:Vec<Ident>=Vec::new();
         let mut events:Vec<Ident>=Vec::new();
         let mut onentrys:Vec<Ident>=Vec::new();
@@ -1396,8 +5049,28 @@ impl Parse for StatemachineInfo {
         let mut guards:Vec<Ident>=Vec::new();
         let mut actions:Vec<Ident>=Vec::new();
         let mut state_transitions:Vec<StateInfo>=Vec::new();
+        let mut scenarios:Vec<ScenarioInfo>=Vec::new();
 
         loop{ // do/while loop, at least one state is required
+            // A state may be named Scenario, too
+            if input.peek(kw::Scenario) && input.peek2(Ident) {
+                let scenario_keyword=input.parse::<kw::Scenario>()?;
+                if no_std || async_handlers {
+                    return Err(syn::parse::Error::new(
+                        scenario_keyword.span,
+                        "Scenarios can't be combined with options no_std and async"));
+                }
+                let scenario=Self::parse_scenario(input, event_payload_type.is_some())?;
+                if scenarios.iter().any(|it| it.name==scenario.name) {
+                    return Err(syn::parse::Error::new(scenario.name.span(), "Duplicate scenario name"));
+                }
+                scenarios.push(scenario);
+                if input.is_empty() && !states.is_empty() {
+                    break;
+                }
+                continue;
+            }
+
             // Expect state name
             let input2=input.fork(); // required for error message
             let state_ident:Ident=input.parse()?;
@@ -1422,22 +5095,70 @@ impl Parse for StatemachineInfo {
         }
 
 
-        let mut state_trans_iter=state_transitions.iter();
-        while let Some(si)=state_trans_iter.next() {
-            let mut trans_iter=si.transitions.iter();
-            while let Some(ti)=trans_iter.next(){
+        for si in &state_transitions {
+            for ti in &si.transitions {
                 if states.iter().find(|st| **st==ti.target_state).is_none(){
                     return Err(syn::parse::Error::new(ti.target_state.span(), "Target state is not defined"));
                 }
             }
         }
 
+        if !states.contains(&initial_state) {
+            return Err(syn::parse::Error::new(initial_state.span(), "Initial state is not defined"));
+        }
+
+        for function in guards.iter().chain(&actions).chain(&onentrys).chain(&onexits).chain(&unexpected_event_handler) {
+            if OBSERVER_FUNCTIONS.contains(&function.to_string().as_str()) {
+                return Err(syn::parse::Error::new(function.span(),
+                    format!("{} is an observer function of the handler trait and can't be used as handler name",
+                            function)));
+            }
+        }
+
+        let analysis_warnings=Self::analyze_state_graph(&initial_state, &state_transitions, &events);
+        if strict {
+            let mut errors=analysis_warnings.iter()
+                .map(|(span, message)| syn::parse::Error::new(*span, message));
+            if let Some(mut error)=errors.next() {
+                errors.for_each(|e| error.combine(e));
+                return Err(error);
+            }
+        }
+
+        for step in scenarios.iter().flat_map(|scenario| &scenario.steps) {
+            if !events.contains(&step.event) {
+                return Err(syn::parse::Error::new(step.event.span(), "Event is not defined"));
+            }
+            if !states.contains(&step.expected_state) {
+                return Err(syn::parse::Error::new(step.expected_state.span(), "Expected state is not defined"));
+            }
+            if let Some((guard, _))=step.guard_values.iter().find(|(guard, _)| !guards.contains(guard)) {
+                return Err(syn::parse::Error::new(guard.span(), "Guard is not defined"));
+            }
+        }
+
+        // The mock handler records calls as variants named by the handler functions in CamelCase, which must differ
+        if mock_handler || !scenarios.is_empty() {
+            let mut variants: Vec<(String, &Ident)>=Vec::new();
+            for function in unexpected_event_handler.iter().chain(&onentrys).chain(&onexits).chain(&actions) {
+                let variant=to_camel_case(function);
+                if let Some((_, other))=variants.iter().find(|(v, other)| *v == variant && *other != function) {
+                    return Err(syn::parse::Error::new(function.span(),
+                        format!("{} and {} both map to the mock call variant {}", other, function, variant)));
+                }
+                variants.push((variant, function));
+            }
+        }
+
         let has_payload= event_payload_type.is_some();
         Ok(StatemachineInfo {
             sm_name: sm_name_ident,
             initial_state,
             event_payload_type,
             unexpected_event_handler,
+            event_queue_capacity,
+            max_steps,
+            history_capacity,
             states,
             events,
             onentrys,
@@ -1445,8 +5166,22 @@ impl Parse for StatemachineInfo {
             guards,
             actions,
             state_transitions,
+            scenarios,
+            analysis_warnings,
             options: Options{
                 has_payload,
+                thread_safe,
+                no_std,
+                direct_ownership,
+                async_handlers,
+                legacy_start,
+                mock_handler,
+                random_walk,
+                strict,
+                log,
+                tracing,
+                serde,
+                coverage,
                 action_handler_with_transition_info,
                 entry_handler_with_transition_info,
                 exit_handler_with_transition_info,
@@ -1458,26 +5193,139 @@ impl Parse for StatemachineInfo {
 
 impl StatemachineInfo {
 
+    // Analyzes the graph of states and transitions, returning the findings: states not reachable from the initial
+    // state, states no final state is reachable from (if there are final states), and events only handled in
+    // unreachable states. Guards are assumed to accept events eventually.
+    fn analyze_state_graph(initial_state: &Ident, state_transitions: &[StateInfo], events: &[Ident])
+        -> Vec<(Span, String)>
+    {
+        let successors=|state: &Ident| -> Vec<&Ident> {
+            state_transitions.iter()
+                .filter(|st| st.state == *state)
+                .flat_map(|st| st.transitions.iter().map(|t| &t.target_state))
+                .collect()
+        };
+
+        // Forward search from the initial state
+        let mut reachable: Vec<&Ident>=vec![initial_state];
+        let mut index=0;
+        while index < reachable.len() {
+            for target in successors(reachable[index]) {
+                if !reachable.contains(&target) {
+                    reachable.push(target);
+                }
+            }
+            index+=1;
+        }
+
+        // Backward fixpoint from the final states
+        let mut terminating: Vec<&Ident>=state_transitions.iter()
+            .filter(|st| st.transitions.is_empty())
+            .map(|st| &st.state)
+            .collect();
+        let has_final_states=!terminating.is_empty();
+        let mut changed=true;
+        while changed {
+            changed=false;
+            for st in state_transitions {
+                if !terminating.contains(&&st.state)
+                    && st.transitions.iter().any(|t| terminating.contains(&&t.target_state)) {
+                    terminating.push(&st.state);
+                    changed=true;
+                }
+            }
+        }
+
+        let mut warnings: Vec<(Span, String)>=Vec::new();
+        for st in state_transitions {
+            if !reachable.contains(&&st.state) {
+                warnings.push((st.state.span(),
+                               format!("State {} is not reachable from initial state {}", st.state, initial_state)));
+            } else if has_final_states && !terminating.contains(&&st.state) {
+                warnings.push((st.state.span(), format!("No final state is reachable from state {}", st.state)));
+            }
+        }
+        for event in events {
+            let handled=state_transitions.iter()
+                .filter(|st| reachable.contains(&&st.state))
+                .any(|st| st.transitions.iter().any(|t| t.event == *event));
+            if !handled {
+                warnings.push((event.span(),
+                               format!("Event {} is handled in unreachable states only, and can never be handled",
+                                       event)));
+            }
+        }
+        warnings
+    }
+
     fn parse_options(input: ParseStream) -> Result<Options> {
         let mut action_handler_with_transition_info = false;
         let mut entry_handler_with_transition_info = false;
         let mut exit_handler_with_transition_info = false;
         let mut guard_with_transition_info = false;
+        let mut thread_safe = false;
+        let mut no_std = false;
+        let mut direct_ownership = false;
+        let mut async_handlers = false;
+        let mut legacy_start = false;
+        let mut mock_handler = false;
+        let mut random_walk = false;
+        let mut strict = false;
+        let mut log = false;
+        let mut tracing = false;
+        let mut serde = false;
+        let mut coverage = false;
         if input.peek(token::Bracket) {
             let in_options;
             bracketed!(in_options in input);
             while !in_options.is_empty() {
+                // `async` is a keyword, and can't be parsed as identifier
+                if in_options.peek(Token![async]) {
+                    let opt = in_options.parse::<Token![async]>()?;
+                    if thread_safe {
+                        return Err(syn::parse::Error::new(
+                            opt.span,
+                            "Option thread_safe can't be combined with async"));
+                    }
+                    async_handlers = true;
+                    if !in_options.is_empty() {
+                        in_options.parse::<Token![,]>()?;
+                    }
+                    continue;
+                }
                 let opt: Ident = in_options.parse()?;
                 match opt.to_string().as_str() {
                     "action_handler_with_transition_info" => action_handler_with_transition_info = true,
                     "entry_handler_with_transition_info" => entry_handler_with_transition_info = true,
                     "exit_handler_with_transition_info" => exit_handler_with_transition_info = true,
                     "guard_with_transition_info" => guard_with_transition_info = true,
+                    "thread_safe" => thread_safe = true,
+                    "no_std" => no_std = true,
+                    "direct_ownership" => direct_ownership = true,
+                    "legacy_start" => legacy_start = true,
+                    "mock_handler" => mock_handler = true,
+                    "random_walk" => random_walk = true,
+                    "strict" => strict = true,
+                    "log" => log = true,
+                    "tracing" => tracing = true,
+                    "serde" => serde = true,
+                    "coverage" => coverage = true,
                     &_ => return Err(syn::parse::Error::new(
                         opt.span(),
                         "Unknown option identifier. Supported optiones are action_handler_with_transition_info, \
-                        entry_handler_with_transition_info, exit_handler_with_transition_info, and \
-                        guard_with_transition_info")),
+                        entry_handler_with_transition_info, exit_handler_with_transition_info, \
+                        guard_with_transition_info, thread_safe, no_std, direct_ownership, async, legacy_start, \
+                        mock_handler, random_walk, strict, log, tracing, serde, and coverage")),
+                }
+                if (mock_handler || random_walk) && no_std {
+                    return Err(syn::parse::Error::new(
+                        opt.span(),
+                        "Options mock_handler and random_walk can't be combined with no_std"));
+                }
+                if thread_safe && (no_std || direct_ownership || async_handlers) {
+                    return Err(syn::parse::Error::new(
+                        opt.span(),
+                        "Option thread_safe can't be combined with no_std, direct_ownership, or async"));
                 }
                 if !in_options.is_empty() {
                     in_options.parse::<Token![,]>()?;
@@ -1486,6 +5334,18 @@ impl StatemachineInfo {
         }
         Ok(Options{
             has_payload: false,
+            thread_safe,
+            no_std,
+            direct_ownership,
+            async_handlers,
+            legacy_start,
+            mock_handler,
+            random_walk,
+            strict,
+            log,
+            tracing,
+            serde,
+            coverage,
             action_handler_with_transition_info,
             entry_handler_with_transition_info,
             exit_handler_with_transition_info,
@@ -1495,9 +5355,9 @@ impl StatemachineInfo {
 
 
     fn parse_state_content(
-        mut events: &mut Vec<Ident>,
+        events: &mut Vec<Ident>,
         onentrys: &mut Vec<Ident>, onexits: &mut Vec<Ident>,
-        mut guards: &mut Vec<Ident>, mut actions: &mut Vec<Ident>,
+        guards: &mut Vec<Ident>, actions: &mut Vec<Ident>,
         state_ident: &Ident, in_state: &ParseBuffer
     ) -> Result<StateInfo> {
         let mut onentry= None;
@@ -1526,8 +5386,8 @@ impl StatemachineInfo {
             }
 
 
-                transitions = Self::parse_transition_lines(&mut events, &mut guards, &mut actions,
-                                                                 &in_state)?;
+                transitions = Self::parse_transition_lines(events, guards, actions,
+                                                                 in_state)?;
         }
         Ok(StateInfo {
             state: state_ident.clone(),
@@ -1538,6 +5398,55 @@ impl StatemachineInfo {
     }
 
 
+    // Parses a scenario after its keyword: the test name, and the braced steps `Event -> State`, separated by
+    // semicolons. With an event payload, each event takes its payload in parentheses.
+    fn parse_scenario(input: ParseStream, has_payload: bool) -> Result<ScenarioInfo> {
+        let name: Ident=input.parse()?;
+        let in_scenario;
+        braced!(in_scenario in input);
+
+        let mut steps: Vec<ScenarioStep>=Vec::new();
+        while !in_scenario.is_empty() {
+            let event: Ident=in_scenario.parse()?;
+            let payload=if in_scenario.peek(token::Paren) {
+                let in_payload;
+                parenthesized!(in_payload in in_scenario);
+                Some(in_payload.parse::<Expr>()?)
+            } else {None};
+            if has_payload && payload.is_none() {
+                return Err(syn::parse::Error::new(event.span(), "Event payload expected in parentheses"));
+            }
+            if !has_payload && payload.is_some() {
+                return Err(syn::parse::Error::new(
+                    event.span(),
+                    "Event payload given, but EventPayload is not defined"));
+            }
+            let mut guard_values: Vec<(Ident, LitBool)>=Vec::new();
+            if in_scenario.peek(token::Bracket) {
+                let in_guards;
+                bracketed!(in_guards in in_scenario);
+                while !in_guards.is_empty() {
+                    let guard: Ident=in_guards.parse()?;
+                    in_guards.parse::<Token![=]>()?;
+                    let value: LitBool=in_guards.parse()?;
+                    guard_values.push((guard, value));
+                    if !in_guards.is_empty() {
+                        in_guards.parse::<Token![,]>()?;
+                    }
+                }
+            }
+            in_scenario.parse::<Token![->]>()?;
+            let expected_state: Ident=in_scenario.parse()?;
+            steps.push(ScenarioStep{event, payload, guard_values, expected_state});
+
+            if !in_scenario.is_empty() {
+                in_scenario.parse::<Token![;]>()?;
+            }
+        }
+        Ok(ScenarioInfo{name, steps})
+    }
+
+
 
     fn parse_transition_lines(
         events: &mut Vec<Ident>, guards: &mut Vec<Ident>, actions: &mut Vec<Ident>,
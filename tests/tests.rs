@@ -28,7 +28,7 @@ mod tests {
         action_handler_event: Option<TestStatemachine3Event>,
         action_handler_new_state: Option<TestStatemachine3State>,
         last_state: Option<TestStatemachineState>,
-        last_event: Option<TestStatemachineEvent>,
+        last_event: Option<TestStatemachineEventKind>,
         testsm4: Option<Weak<RefCell<TestStatemachine4<Self>>>>,
         initial_on_entry_called: bool,
         initial_on_entry_old_state: Option<TestStatemachine5State>,
@@ -80,10 +80,10 @@ mod tests {
     }
 
     impl TestStatemachineHandler for StatemachineHandler {
-        fn unexpected_handler(&mut self, state: TestStatemachineState, event: &TestStatemachineEvent) {
+        fn unexpected_handler(&mut self, state: TestStatemachineState, event: TestStatemachineEventKind) {
             self.unexpected_handler_called = true;
             self.last_state = Some(state);
-            self.last_event = Some(event.clone());
+            self.last_event = Some(event);
         }
     }
 
@@ -214,7 +214,7 @@ mod tests {
         assert!(smh.borrow().last_state.is_some());
         assert_eq!(smh.borrow().last_state.clone().unwrap(), TestStatemachineState::MyThirdState);
         assert!(smh.borrow().last_event.is_some());
-        assert_eq!(smh.borrow().last_event.clone().unwrap(), TestStatemachineEvent::MySecondEvent);
+        assert_eq!(smh.borrow().last_event.unwrap(), TestStatemachineEventKind::MySecondEvent);
     }
 
 
@@ -249,6 +249,18 @@ mod tests {
         assert_eq!(sm.get_state(), TestStatemachine2State::MyThirdState);
     }
 
+    #[test]
+    fn next_states_test() {
+        assert_eq!(TestStatemachine2State::MyInitialState.next_states(TestStatemachine2EventKind::MyFirstEvent), [
+            (Some("test_guard"), TestStatemachine2State::MyThirdState),
+            (None, TestStatemachine2State::MyInitialState),
+        ]);
+        assert!(TestStatemachine2State::MyThirdState.next_states(TestStatemachine2EventKind::MyFirstEvent).is_empty());
+        assert_eq!(TestStatemachine2State::TRANSITIONS.len(), 2);
+        assert_eq!(TestStatemachine2State::TRANSITIONS[0], (TestStatemachine2State::MyInitialState,
+            TestStatemachine2EventKind::MyFirstEvent, Some("test_guard"), TestStatemachine2State::MyThirdState));
+    }
+
     statemachine! {
         [action_handler_with_transition_info]
 
@@ -459,4 +471,1465 @@ mod tests {
         assert!(sm.get_handler_ref().event_value.is_some());
         assert_eq!(sm.get_handler_ref().event_value.clone().unwrap(),MyEventPayload::new(1234));
     }
+
+    #[test]
+    fn event_kind_test() {
+        let ev = TestStatemachine6Event::MySecondEvent(MyEventPayload::new(1234));
+        assert_eq!(ev.kind(), TestStatemachine6EventKind::MySecondEvent);
+        assert_eq!(TestStatemachine6EventKind::from(&ev), TestStatemachine6EventKind::MySecondEvent);
+        assert_eq!(TestStatemachine7Event::MyFirstEvent.kind(), TestStatemachine7EventKind::MyFirstEvent);
+    }
+
+    #[test]
+    fn names_test() {
+        assert_eq!(TestStatemachine6State::MySecondState.as_str(), "MySecondState");
+        assert_eq!(TestStatemachine6State::MyInitialState.to_string(), "MyInitialState");
+        assert_eq!("MySecondState".parse::<TestStatemachine6State>(), Ok(TestStatemachine6State::MySecondState));
+        assert_eq!("MyThirdState".parse::<TestStatemachine6State>(), Err(TestStatemachine6ParseError));
+
+        assert_eq!(TestStatemachine6EventKind::MySecondEvent.as_str(), "MySecondEvent");
+        assert_eq!(TestStatemachine6EventKind::MySecondEvent.to_string(), "MySecondEvent");
+        assert_eq!("MySecondEvent".parse::<TestStatemachine6EventKind>(),
+                   Ok(TestStatemachine6EventKind::MySecondEvent));
+        assert!("MyFirstEvent".parse::<TestStatemachine6EventKind>().is_err());
+    }
+
+
+    struct ThreadSafeHandler {
+        action_count: u32,
+    }
+
+    impl TestStatemachine8Handler for ThreadSafeHandler {
+        fn count_action(&mut self) {
+            self.action_count += 1;
+        }
+    }
+
+    statemachine! {
+        [thread_safe]
+        Name                TestStatemachine8
+        InitialState        MyInitialState
+
+        MyInitialState {
+            MyFirstEvent == count_action => MySecondState
+        }
+        MySecondState {
+            MyFirstEvent == count_action => MyInitialState
+        }
+    }
+
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    #[test]
+    fn thread_safe_test() {
+        let sm = std::sync::Arc::new(TestStatemachine8::new(ThreadSafeHandler { action_count: 0 }));
+        assert_send_sync(&*sm);
+
+        let threads: Vec<_> = (0..4).map(|_| {
+            let sm = sm.clone();
+            std::thread::spawn(move || {
+                for _ in 0..50 {
+                    sm.event(TestStatemachine8Event::MyFirstEvent);
+                }
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        let handler = sm.get_handler();
+        assert_eq!(handler.lock().unwrap().action_count, 200);
+        assert_eq!(sm.get_state(), TestStatemachine8State::MyInitialState);
+    }
+
+    struct LockingHandler {
+        sm: std::sync::Arc<std::sync::OnceLock<std::sync::Weak<TestStatemachine26<LockingHandler>>>>,
+    }
+
+    impl TestStatemachine26Handler for LockingHandler {
+        fn lock_handler(&mut self) {
+            let sm = self.sm.get().unwrap().upgrade().unwrap();
+            let _handler = sm.get_handler_ref();
+        }
+    }
+
+    statemachine! {
+        [thread_safe]
+        Name                TestStatemachine26
+        InitialState        MyInitialState
+
+        MyInitialState {
+            MyFirstEvent == lock_handler => MyInitialState
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "get_handler_ref() called from a handler")]
+    fn thread_safe_handler_from_handler_test() {
+        let cell = std::sync::Arc::new(std::sync::OnceLock::new());
+        let sm = std::sync::Arc::new(TestStatemachine26::new(LockingHandler { sm: cell.clone() }));
+        cell.set(std::sync::Arc::downgrade(&sm)).unwrap();
+        sm.event(TestStatemachine26Event::MyFirstEvent);
+    }
+
+
+    struct OwnedHandler {
+        calls: Vec<&'static str>,
+        guard_value: bool,
+    }
+
+    impl TestStatemachine9Handler for OwnedHandler {
+        fn unexpected_handler(&mut self, _ctx: &mut TestStatemachine9Context,
+                              _state: TestStatemachine9State, _event: TestStatemachine9EventKind) {
+            self.calls.push("unexpected_handler");
+        }
+        fn enter_second_state(&mut self, _ctx: &mut TestStatemachine9Context) {
+            self.calls.push("enter_second_state");
+        }
+        fn exit_initial_state(&mut self, _ctx: &mut TestStatemachine9Context) {
+            self.calls.push("exit_initial_state");
+        }
+        fn second_guard(&self) -> bool {
+            self.guard_value
+        }
+        fn action_with_payload(&mut self, _ctx: &mut TestStatemachine9Context, payload: &MyEventPayload) {
+            assert_eq!(payload.value, 42);
+            self.calls.push("action_with_payload");
+        }
+    }
+
+    statemachine! {
+        [no_std]
+        Name                TestStatemachine9
+        InitialState        MyInitialState
+        EventPayload        MyEventPayload
+        UnexpectedHandler   unexpected_handler
+        History             4
+
+        MyInitialState {
+            OnExit exit_initial_state
+            MyFirstEvent[second_guard] == action_with_payload => MySecondState
+        }
+        MySecondState {
+            OnEntry enter_second_state
+        }
+    }
+
+    #[test]
+    fn no_std_test() {
+        let mut sm = TestStatemachine9::new(OwnedHandler { calls: Vec::new(), guard_value: false });
+        assert_eq!(sm.get_state(), TestStatemachine9State::MyInitialState);
+
+        sm.event(TestStatemachine9Event::MyFirstEvent(MyEventPayload::new(42)));
+        assert_eq!(sm.get_state(), TestStatemachine9State::MyInitialState);
+        assert_eq!(sm.get_handler_ref().calls, ["unexpected_handler"]);
+
+        sm.get_handler_mut().guard_value = true;
+        assert!(sm.can_handle(&TestStatemachine9Event::MyFirstEvent(MyEventPayload::new(42))));
+        sm.event(TestStatemachine9Event::MyFirstEvent(MyEventPayload::new(42)));
+        assert_eq!(sm.get_state(), TestStatemachine9State::MySecondState);
+        assert_eq!(sm.history().last(), Some(&TestStatemachine9HistoryEntry {
+            seq: 0,
+            old_state: TestStatemachine9State::MyInitialState,
+            event: TestStatemachine9EventKind::MyFirstEvent,
+            guard: Some("second_guard"),
+            new_state: TestStatemachine9State::MySecondState,
+        }));
+
+        let snapshot = sm.snapshot();
+        assert_eq!(snapshot.state, TestStatemachine9State::MySecondState);
+        assert_eq!(snapshot.pending_events, [None, None, None, None, None, None, None, None]);
+        assert_eq!(snapshot.history[0].as_ref().unwrap().seq, 0);
+        assert!(snapshot.history[1].is_none());
+        let restored = TestStatemachine9::restore(OwnedHandler { calls: Vec::new(), guard_value: false },
+                                                  snapshot.clone());
+        assert_eq!(restored.get_state(), TestStatemachine9State::MySecondState);
+        assert_eq!(restored.snapshot(), snapshot);
+
+        let handler = sm.into_handler();
+        assert_eq!(handler.calls,
+                   ["unexpected_handler", "exit_initial_state", "action_with_payload", "enter_second_state"]);
+    }
+
+
+    impl TestStatemachine10Handler for OwnedHandler {
+        fn action_handler(&mut self, ctx: &mut TestStatemachine10Context) {
+            self.calls.push("action_handler");
+            ctx.post(TestStatemachine10Event::MyActionEvent);
+        }
+        fn enter_second_state(&mut self, _ctx: &mut TestStatemachine10Context) {
+            self.calls.push("enter_second_state");
+        }
+    }
+
+    statemachine! {
+        [direct_ownership]
+        Name                TestStatemachine10
+        InitialState        MyInitialState
+
+        MyInitialState {
+            MyFirstEvent == action_handler => MyInitialState
+            MyActionEvent => MySecondState
+        }
+        MySecondState {
+            OnEntry enter_second_state
+        }
+    }
+
+    #[test]
+    fn direct_ownership_test() {
+        let mut sm = TestStatemachine10::new(OwnedHandler { calls: Vec::new(), guard_value: false });
+        assert_eq!(sm.get_state(), TestStatemachine10State::MyInitialState);
+        sm.event(TestStatemachine10Event::MyFirstEvent);
+        assert_eq!(sm.get_state(), TestStatemachine10State::MySecondState);
+        assert_eq!(sm.get_handler_ref().calls, ["action_handler", "enter_second_state"]);
+    }
+
+
+    impl TestStatemachine12Handler for OwnedHandler {
+        fn post_events(&mut self, ctx: &mut TestStatemachine12Context) {
+            self.calls.push("post_events");
+            ctx.post(TestStatemachine12Event::MyFirstEvent);
+            ctx.post(TestStatemachine12Event::MySecondEvent);
+            if ctx.try_post(TestStatemachine12Event::MyFirstEvent)
+                == Err(TestStatemachine12QueueFullError(TestStatemachine12Event::MyFirstEvent)) {
+                self.calls.push("queue_full");
+            }
+        }
+        fn enter_first_state(&mut self, _ctx: &mut TestStatemachine12Context) {
+            self.calls.push("enter_first_state");
+        }
+        fn enter_initial_state(&mut self, _ctx: &mut TestStatemachine12Context) {
+            self.calls.push("enter_initial_state");
+        }
+    }
+
+    statemachine! {
+        [no_std]
+        Name                TestStatemachine12
+        InitialState        MyInitialState
+        EventQueueCapacity  2
+
+        MyInitialState {
+            OnEntry enter_initial_state
+            MyStartEvent == post_events => MyInitialState
+            MyFirstEvent => MyFirstState
+        }
+        MyFirstState {
+            OnEntry enter_first_state
+            MySecondEvent => MyInitialState
+        }
+    }
+
+    #[test]
+    fn event_queue_test() {
+        let mut sm = TestStatemachine12::new(OwnedHandler { calls: Vec::new(), guard_value: false });
+        sm.event(TestStatemachine12Event::MyStartEvent);
+        assert_eq!(sm.get_state(), TestStatemachine12State::MyInitialState);
+        sm.event(TestStatemachine12Event::MyStartEvent);
+        assert_eq!(sm.get_state(), TestStatemachine12State::MyInitialState);
+
+        let cycle = ["post_events", "queue_full", "enter_initial_state", "enter_first_state", "enter_initial_state"];
+        assert_eq!(sm.get_handler_ref().calls, [&["enter_initial_state"][..], &cycle, &cycle].concat());
+    }
+
+
+    #[derive(PartialEq,Eq,Clone,Copy,Debug,serde::Serialize,serde::Deserialize)]
+    pub struct SerdePayload {
+        value: u32,
+    }
+
+    struct SerdeHandler {
+        guard_value: bool,
+    }
+
+    impl TestStatemachine29Handler for SerdeHandler {
+        fn second_guard(&self) -> bool {
+            self.guard_value
+        }
+    }
+
+    statemachine! {
+        [no_std, serde]
+        Name                TestStatemachine29
+        InitialState        MyInitialState
+        EventPayload        SerdePayload
+        History             4
+
+        MyInitialState {
+            MyFirstEvent[second_guard] => MySecondState
+        }
+        MySecondState {}
+    }
+
+    #[test]
+    fn serde_test() {
+        let ev = TestStatemachine29Event::MyFirstEvent(SerdePayload { value: 42 });
+        let json = serde_json::to_string(&ev).unwrap();
+        assert_eq!(json, r#"{"MyFirstEvent":{"value":42}}"#);
+        assert_eq!(serde_json::from_str::<TestStatemachine29Event>(&json).unwrap(), ev);
+        assert_eq!(serde_json::to_string(&TestStatemachine29State::MySecondState).unwrap(), r#""MySecondState""#);
+
+        let mut sm = TestStatemachine29::new(SerdeHandler { guard_value: true });
+        sm.event(ev);
+        let snapshot = sm.snapshot();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert!(json.contains(r#""guard":"second_guard""#));
+        assert_eq!(serde_json::from_str::<TestStatemachine29Snapshot>(&json).unwrap(), snapshot);
+
+        let json = json.replace("second_guard", "unknown_guard");
+        assert!(serde_json::from_str::<TestStatemachine29Snapshot>(&json).is_err());
+    }
+
+
+    struct ChainHandler {
+        remaining: u32,
+        sm: Weak<RefCell<TestStatemachine13<Self>>>,
+    }
+
+    impl TestStatemachine13Handler for ChainHandler {
+        fn post_next(&mut self) {
+            if self.remaining > 0 {
+                self.remaining -= 1;
+                Weak::upgrade(&self.sm).unwrap().borrow().event_from_handler(TestStatemachine13Event::MyNextEvent);
+            }
+        }
+    }
+
+    statemachine! {
+        Name                TestStatemachine13
+        InitialState        MyInitialState
+
+        MyInitialState {
+            MyNextEvent == post_next => MyInitialState
+        }
+    }
+
+    #[test]
+    fn long_event_chain_test() {
+        let sm = Rc::new(RefCell::new(TestStatemachine13::new(ChainHandler { remaining: 1_000_000, sm: Weak::new() })));
+        sm.borrow().get_handler_mut().sm = Rc::downgrade(&sm);
+        sm.borrow().event(TestStatemachine13Event::MyNextEvent);
+        assert_eq!(sm.borrow().get_handler_ref().remaining, 0);
+    }
+
+    struct QueueHandler;
+
+    impl TestStatemachine27Handler for QueueHandler {}
+
+    statemachine! {
+        Name                TestStatemachine27
+        InitialState        MyInitialState
+        EventQueueCapacity  1
+
+        MyInitialState {
+            MyFirstEvent => MyInitialState
+        }
+    }
+
+    #[test]
+    fn bounded_event_queue_test() {
+        let sm = TestStatemachine27::new(QueueHandler);
+        sm.event_from_handler(TestStatemachine27Event::MyFirstEvent);
+        assert_eq!(sm.try_event_from_handler(TestStatemachine27Event::MyFirstEvent),
+                   Err(TestStatemachine27QueueFullError(TestStatemachine27Event::MyFirstEvent)));
+    }
+
+    #[test]
+    #[should_panic(expected = "event_from_handler() called while the event queue is full")]
+    fn bounded_event_queue_panic_test() {
+        let sm = TestStatemachine27::new(QueueHandler);
+        sm.event_from_handler(TestStatemachine27Event::MyFirstEvent);
+        sm.event_from_handler(TestStatemachine27Event::MyFirstEvent);
+    }
+
+
+    impl TestStatemachine14Handler for OwnedHandler {
+        fn post_again(&mut self, ctx: &mut TestStatemachine14Context) {
+            self.calls.push("post_again");
+            ctx.post(TestStatemachine14Event::MyFirstEvent);
+        }
+    }
+
+    statemachine! {
+        [direct_ownership]
+        Name                TestStatemachine14
+        InitialState        MyInitialState
+        MaxSteps            10
+
+        MyInitialState {
+            MyFirstEvent == post_again => MyInitialState
+        }
+    }
+
+    #[test]
+    fn max_steps_test() {
+        let mut sm = TestStatemachine14::new(OwnedHandler { calls: Vec::new(), guard_value: false });
+        assert_eq!(sm.event(TestStatemachine14Event::MyFirstEvent),
+                   TestStatemachine14Outcome::StepLimitExceeded { state: TestStatemachine14State::MyInitialState });
+        assert_eq!(sm.get_handler_ref().calls.len(), 10);
+
+        // The queued event was dropped, the statemachine stays usable
+        sm.get_handler_mut().calls.clear();
+        assert_eq!(sm.snapshot().pending_events, []);
+        assert_eq!(sm.event(TestStatemachine14Event::MyFirstEvent),
+                   TestStatemachine14Outcome::StepLimitExceeded { state: TestStatemachine14State::MyInitialState });
+        assert_eq!(sm.get_handler_ref().calls.len(), 10);
+    }
+
+
+    struct ReentrantHandler {
+        calls: Vec<&'static str>,
+        sm: Weak<TestStatemachine15<Self>>,
+    }
+
+    impl TestStatemachine15Handler for ReentrantHandler {
+        fn send_events(&mut self) {
+            self.calls.push("send_events");
+            let sm = Weak::upgrade(&self.sm).unwrap();
+            assert_eq!(sm.event(TestStatemachine15Event::MySecondEvent), TestStatemachine15Outcome::Queued);
+            assert_eq!(sm.event(TestStatemachine15Event::MyThirdEvent), TestStatemachine15Outcome::Queued);
+            assert_eq!(sm.event(TestStatemachine15Event::MyFirstEvent),
+                       TestStatemachine15Outcome::QueueFull(TestStatemachine15Event::MyFirstEvent));
+            self.calls.push("send_events_done");
+        }
+        fn enter_second_state(&mut self) {
+            self.calls.push("enter_second_state");
+        }
+        fn enter_third_state(&mut self) {
+            self.calls.push("enter_third_state");
+        }
+    }
+
+    statemachine! {
+        Name                TestStatemachine15
+        InitialState        MyInitialState
+        EventQueueCapacity  2
+
+        MyInitialState {
+            MyFirstEvent == send_events => MyInitialState
+            MySecondEvent => MySecondState
+        }
+        MySecondState {
+            OnEntry enter_second_state
+            MyThirdEvent => MyThirdState
+        }
+        MyThirdState {
+            OnEntry enter_third_state
+        }
+    }
+
+    #[test]
+    fn reentrant_event_test() {
+        let sm = Rc::new(TestStatemachine15::new(ReentrantHandler { calls: Vec::new(), sm: Weak::new() }));
+        sm.get_handler_mut().sm = Rc::downgrade(&sm);
+        sm.event(TestStatemachine15Event::MyFirstEvent);
+        assert_eq!(sm.get_state(), TestStatemachine15State::MyThirdState);
+        assert_eq!(sm.get_handler_ref().calls,
+                   ["send_events", "send_events_done", "enter_second_state", "enter_third_state"]);
+    }
+
+
+    struct ThreadSafeReentrantHandler {
+        action_count: u32,
+        sm: std::sync::Weak<TestStatemachine16<Self>>,
+    }
+
+    impl TestStatemachine16Handler for ThreadSafeReentrantHandler {
+        fn count_and_send(&mut self) {
+            self.action_count += 1;
+            std::sync::Weak::upgrade(&self.sm).unwrap().event(TestStatemachine16Event::MySecondEvent);
+        }
+        fn count_action(&mut self) {
+            self.action_count += 1;
+        }
+    }
+
+    statemachine! {
+        [thread_safe]
+        Name                TestStatemachine16
+        InitialState        MyInitialState
+
+        MyInitialState {
+            MyFirstEvent == count_and_send => MySecondState
+        }
+        MySecondState {
+            MySecondEvent == count_action => MyInitialState
+        }
+    }
+
+    #[test]
+    fn thread_safe_reentrant_event_test() {
+        let sm = std::sync::Arc::new(TestStatemachine16::new(
+            ThreadSafeReentrantHandler { action_count: 0, sm: std::sync::Weak::new() }));
+        sm.get_handler_mut().sm = std::sync::Arc::downgrade(&sm);
+
+        let threads: Vec<_> = (0..4).map(|_| {
+            let sm = sm.clone();
+            std::thread::spawn(move || {
+                for _ in 0..50 {
+                    sm.event(TestStatemachine16Event::MyFirstEvent);
+                }
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert_eq!(sm.get_state(), TestStatemachine16State::MyInitialState);
+        assert_eq!(sm.get_handler_ref().action_count, 400);
+    }
+
+
+    struct NoopWaker;
+    impl std::task::Wake for NoopWaker {
+        fn wake(self: std::sync::Arc<Self>) {}
+    }
+
+    // Minimal executor for the async tests: polls the future until it is ready
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = std::task::Waker::from(std::sync::Arc::new(NoopWaker));
+        let mut cx = std::task::Context::from_waker(&waker);
+        loop {
+            if let std::task::Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    // Future returning Pending once before completing, to simulate an async operation
+    struct YieldOnce(bool);
+    impl std::future::Future for YieldOnce {
+        type Output = ();
+        fn poll(mut self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> std::task::Poll<()> {
+            if self.0 {
+                std::task::Poll::Ready(())
+            } else {
+                self.0 = true;
+                std::task::Poll::Pending
+            }
+        }
+    }
+
+    impl TestStatemachine11Handler for OwnedHandler {
+        async fn async_guard(&self, _state: TestStatemachine11State, event: &TestStatemachine11Event) -> bool {
+            YieldOnce(false).await;
+            self.guard_value && event.kind() == TestStatemachine11EventKind::MyFirstEvent
+        }
+        async fn async_action(&mut self, ctx: &mut TestStatemachine11Context, payload: &MyEventPayload) {
+            YieldOnce(false).await;
+            self.calls.push("async_action");
+            if payload.value == 1 {
+                ctx.post(TestStatemachine11Event::MySecondEvent(MyEventPayload::new(2)));
+            }
+        }
+        async fn enter_second_state(&mut self, _ctx: &mut TestStatemachine11Context) {
+            self.calls.push("enter_second_state");
+        }
+    }
+
+    statemachine! {
+        [async, guard_with_transition_info]
+        Name                TestStatemachine11
+        InitialState        MyInitialState
+        EventPayload        MyEventPayload
+
+        MyInitialState {
+            MyFirstEvent[async_guard] == async_action => MyInitialState
+            MySecondEvent == async_action => MySecondState
+        }
+        MySecondState {
+            OnEntry enter_second_state
+        }
+    }
+
+    #[test]
+    fn async_test() {
+        let mut sm = TestStatemachine11::new(OwnedHandler { calls: Vec::new(), guard_value: false });
+        assert!(!block_on(sm.can_handle(&TestStatemachine11Event::MyFirstEvent(MyEventPayload::new(1)))));
+        block_on(sm.event(TestStatemachine11Event::MyFirstEvent(MyEventPayload::new(1))));
+        assert_eq!(sm.get_state(), TestStatemachine11State::MyInitialState);
+        assert!(sm.get_handler_ref().calls.is_empty());
+
+        sm.get_handler_mut().guard_value = true;
+        block_on(sm.event(TestStatemachine11Event::MyFirstEvent(MyEventPayload::new(1))));
+        assert_eq!(sm.get_state(), TestStatemachine11State::MySecondState);
+        assert_eq!(sm.get_handler_ref().calls, ["async_action", "async_action", "enter_second_state"]);
+    }
+
+    #[test]
+    fn run_test() {
+        // Runs until the channel is closed
+        let (sender, receiver) = std::sync::mpsc::channel();
+        sender.send(TestStatemachineEvent::MyThirdEvent).unwrap();
+        sender.send(TestStatemachineEvent::MySecondEvent).unwrap();
+        drop(sender);
+        let sm = TestStatemachine::new(StatemachineHandler::new());
+        let handler = sm.run(receiver);
+        assert!(!handler.unexpected_handler_called);
+
+        // Runs until a final state is reached, in another thread
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let runner = std::thread::spawn(move || {
+            let sm = TestStatemachine10::new(OwnedHandler { calls: Vec::new(), guard_value: false });
+            sm.run(receiver)
+        });
+        sender.send(TestStatemachine10Event::MyFirstEvent).unwrap();
+        let handler = runner.join().unwrap();
+        assert_eq!(handler.calls, ["action_handler", "enter_second_state"]);
+        assert!(sender.send(TestStatemachine10Event::MyFirstEvent).is_err());
+
+        assert!(TestStatemachine10State::MySecondState.is_final());
+        assert!(!TestStatemachine10State::MyInitialState.is_final());
+        assert!(!TestStatemachine8State::MyInitialState.is_final());
+    }
+
+    #[test]
+    #[should_panic(expected = "run() can't return the handler, it is still shared via get_handler()")]
+    fn run_shared_handler_test() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        drop(sender);
+        let sm = TestStatemachine::new(StatemachineHandler::new());
+        let _handler = sm.get_handler();
+        sm.run(receiver);
+    }
+
+    struct EventList<'a>(&'a mut std::collections::VecDeque<TestStatemachine11Event>);
+
+    impl TestStatemachine11EventStream for EventList<'_> {
+        fn poll_next(mut self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>)
+            -> std::task::Poll<Option<TestStatemachine11Event>> {
+            std::task::Poll::Ready(self.0.pop_front())
+        }
+    }
+
+    #[test]
+    fn async_run_test() {
+        let mut events = std::collections::VecDeque::from([
+            TestStatemachine11Event::MySecondEvent(MyEventPayload::new(2)),
+            TestStatemachine11Event::MySecondEvent(MyEventPayload::new(3)),
+        ]);
+        let sm = TestStatemachine11::new(OwnedHandler { calls: Vec::new(), guard_value: false });
+        let handler = block_on(sm.run(EventList(&mut events)));
+        assert_eq!(handler.calls, ["async_action", "enter_second_state"]);
+        assert_eq!(events.len(), 1);
+    }
+
+
+    impl TestStatemachine7Handler for StatemachineHandler {
+        fn accept_guard(&self) -> bool {
+            self.guard_value
+        }
+    }
+
+    statemachine! {
+        Name                TestStatemachine7
+        InitialState        MyInitialState
+
+        MyInitialState {
+            MyFirstEvent[accept_guard] => MySecondState
+            MySecondEvent => MySecondState
+        }
+        MySecondState {}
+    }
+
+    #[test]
+    fn accepted_events_test() {
+        let sm = TestStatemachine7::new(StatemachineHandler::new());
+        assert_eq!(sm.accepted_events(), [TestStatemachine7EventKind::MyFirstEvent,
+            TestStatemachine7EventKind::MySecondEvent]);
+        assert!(!sm.can_handle(&TestStatemachine7Event::MyFirstEvent));
+        assert!(sm.can_handle(&TestStatemachine7Event::MySecondEvent));
+
+        sm.get_handler_mut().guard_value = true;
+        assert!(sm.can_handle(&TestStatemachine7Event::MyFirstEvent));
+        assert_eq!(sm.get_state(), TestStatemachine7State::MyInitialState);
+
+        sm.event(TestStatemachine7Event::MyFirstEvent);
+        assert_eq!(sm.get_state(), TestStatemachine7State::MySecondState);
+        assert!(sm.accepted_events().is_empty());
+        assert!(!sm.can_handle(&TestStatemachine7Event::MySecondEvent));
+    }
+
+    statemachine! {
+        Name                TestStatemachine19
+        InitialState        History
+        History             2
+
+        History {
+            MyFirstEvent => MySecondState
+        }
+        MySecondState {
+            MySecondEvent => History
+        }
+    }
+
+    impl TestStatemachine19Handler for StatemachineHandler {}
+
+    #[test]
+    fn history_test() {
+        let sm = TestStatemachine19::new(StatemachineHandler::new());
+        assert!(sm.history().is_empty());
+        let start = std::time::SystemTime::now();
+        sm.event(TestStatemachine19Event::MyFirstEvent);
+        sm.event(TestStatemachine19Event::MyFirstEvent);
+        sm.event(TestStatemachine19Event::MySecondEvent);
+        sm.event(TestStatemachine19Event::MyFirstEvent);
+
+        let history = sm.history();
+        assert_eq!(history.len(), 2);
+        let entries: Vec<_> = history.iter()
+            .map(|e| (e.seq, e.old_state.clone(), e.event, e.guard, e.new_state.clone()))
+            .collect();
+        assert_eq!(entries, [
+            (1, TestStatemachine19State::MySecondState, TestStatemachine19EventKind::MySecondEvent, None,
+             TestStatemachine19State::History),
+            (2, TestStatemachine19State::History, TestStatemachine19EventKind::MyFirstEvent, None,
+             TestStatemachine19State::MySecondState),
+        ]);
+        assert!(history.iter().all(|e| e.timestamp >= start));
+    }
+
+    #[test]
+    fn snapshot_test() {
+        let sm = TestStatemachine19::new(StatemachineHandler::new());
+        sm.event(TestStatemachine19Event::MyFirstEvent);
+        sm.event_from_handler(TestStatemachine19Event::MySecondEvent);
+        let snapshot = sm.snapshot();
+        assert_eq!(snapshot.state, TestStatemachine19State::MySecondState);
+        assert_eq!(snapshot.pending_events, [TestStatemachine19Event::MySecondEvent]);
+        assert_eq!(snapshot.history.len(), 1);
+
+        let restored = TestStatemachine19::restore(StatemachineHandler::new(), snapshot.clone());
+        assert_eq!(restored.snapshot(), snapshot);
+        restored.event(TestStatemachine19Event::MyFirstEvent);
+        assert_eq!(restored.get_state(), TestStatemachine19State::History);
+        assert_eq!(restored.history().last().unwrap().seq, 1);
+    }
+
+    #[test]
+    fn outcome_test() {
+        let sm = TestStatemachine7::new(StatemachineHandler::new());
+        assert_eq!(sm.event(TestStatemachine7Event::MyFirstEvent),
+                   TestStatemachine7Outcome::Blocked { state: TestStatemachine7State::MyInitialState });
+        assert_eq!(sm.event(TestStatemachine7Event::MySecondEvent),
+                   TestStatemachine7Outcome::Transition {
+                       old_state: TestStatemachine7State::MyInitialState,
+                       new_state: TestStatemachine7State::MySecondState,
+                   });
+        assert_eq!(sm.event(TestStatemachine7Event::MySecondEvent),
+                   TestStatemachine7Outcome::Ignored { state: TestStatemachine7State::MySecondState });
+
+        let sm = TestStatemachine::new(StatemachineHandler::new());
+        assert_eq!(sm.event(TestStatemachineEvent::MySecondEvent),
+                   TestStatemachineOutcome::Unexpected { state: TestStatemachineState::MyInitialState });
+        assert!(sm.get_handler_ref().unexpected_handler_called);
+    }
+
+
+    #[derive(Default)]
+    struct ObserverHandler {
+        transitions: Vec<(TestStatemachine17State, TestStatemachine17EventKind, TestStatemachine17State)>,
+        ignored: Vec<(TestStatemachine17State, TestStatemachine17EventKind)>,
+    }
+
+    impl TestStatemachine17Handler for ObserverHandler {
+        fn on_transition(&mut self, old_state: TestStatemachine17State, event: &TestStatemachine17Event,
+                         new_state: TestStatemachine17State) {
+            self.transitions.push((old_state, event.kind(), new_state));
+        }
+        fn on_event_ignored(&mut self, state: TestStatemachine17State, event: &TestStatemachine17Event) {
+            self.ignored.push((state, event.kind()));
+        }
+        fn reject_guard(&self) -> bool {
+            false
+        }
+    }
+
+    statemachine! {
+        Name                TestStatemachine17
+        InitialState        MyInitialState
+
+        MyInitialState {
+            MyFirstEvent[reject_guard] => MyInitialState
+            MySecondEvent => MySecondState
+        }
+        MySecondState {
+            MyFirstEvent => MyInitialState
+        }
+    }
+
+    #[test]
+    fn observer_test() {
+        let sm = TestStatemachine17::new(ObserverHandler::default());
+        sm.event(TestStatemachine17Event::MyFirstEvent);
+        sm.event(TestStatemachine17Event::MySecondEvent);
+        sm.event(TestStatemachine17Event::MySecondEvent);
+        sm.event(TestStatemachine17Event::MyFirstEvent);
+
+        assert_eq!(sm.get_handler_ref().transitions, [
+            (TestStatemachine17State::MyInitialState, TestStatemachine17EventKind::MySecondEvent,
+             TestStatemachine17State::MySecondState),
+            (TestStatemachine17State::MySecondState, TestStatemachine17EventKind::MyFirstEvent,
+             TestStatemachine17State::MyInitialState),
+        ]);
+        assert_eq!(sm.get_handler_ref().ignored, [
+            (TestStatemachine17State::MyInitialState, TestStatemachine17EventKind::MyFirstEvent),
+            (TestStatemachine17State::MySecondState, TestStatemachine17EventKind::MySecondEvent),
+        ]);
+    }
+
+
+    #[derive(Default)]
+    struct LifecycleHandler {
+        calls: Vec<&'static str>,
+    }
+
+    impl TestStatemachine20Handler for LifecycleHandler {
+        fn enter_initial_state(&mut self) {
+            self.calls.push("enter_initial_state");
+        }
+        fn exit_initial_state(&mut self) {
+            self.calls.push("exit_initial_state");
+        }
+        fn enter_second_state(&mut self) {
+            self.calls.push("enter_second_state");
+        }
+    }
+
+    impl TestStatemachine21Handler for LifecycleHandler {
+        fn enter_initial_state(&mut self) {
+            self.calls.push("enter_initial_state");
+        }
+        fn exit_initial_state(&mut self) {
+            self.calls.push("exit_initial_state");
+        }
+        fn enter_second_state(&mut self) {
+            self.calls.push("enter_second_state");
+        }
+    }
+
+    statemachine! {
+        Name                TestStatemachine20
+        InitialState        MyInitialState
+
+        MyInitialState {
+            OnEntry enter_initial_state
+            OnExit exit_initial_state
+            MyFirstEvent => MySecondState
+        }
+        MySecondState {
+            OnEntry enter_second_state
+        }
+    }
+
+    statemachine! {
+        [legacy_start]
+        Name                TestStatemachine21
+        InitialState        MyInitialState
+
+        MyInitialState {
+            OnEntry enter_initial_state
+            OnExit exit_initial_state
+            MyFirstEvent => MySecondState
+        }
+        MySecondState {
+            OnEntry enter_second_state
+        }
+    }
+
+    #[test]
+    fn start_stop_test() {
+        let sm = TestStatemachine20::new(LifecycleHandler::default());
+        assert!(!sm.is_running());
+        sm.start();
+        sm.start();
+        assert!(sm.is_running());
+        assert_eq!(sm.get_handler_ref().calls, ["enter_initial_state"]);
+
+        sm.stop();
+        assert!(!sm.is_running());
+        assert_eq!(sm.event(TestStatemachine20Event::MyFirstEvent), TestStatemachine20Outcome::Stopped);
+        assert_eq!(sm.get_handler_ref().calls, ["enter_initial_state", "exit_initial_state"]);
+        sm.start();
+        assert!(sm.is_running());
+        assert_eq!(sm.get_handler_ref().calls, ["enter_initial_state", "exit_initial_state", "enter_initial_state"]);
+    }
+
+    #[test]
+    fn reset_test() {
+        let sm = TestStatemachine20::new(LifecycleHandler::default());
+        let handler = sm.get_handler();
+        sm.event(TestStatemachine20Event::MyFirstEvent);
+        sm.event_from_handler(TestStatemachine20Event::MyFirstEvent);
+        sm.reset();
+        assert_eq!(sm.get_state(), TestStatemachine20State::MyInitialState);
+        assert!(sm.snapshot().pending_events.is_empty());
+        assert_eq!(handler.borrow().calls,
+                   ["enter_initial_state", "exit_initial_state", "enter_second_state", "enter_initial_state"]);
+
+        sm.stop();
+        sm.reset();
+        assert!(sm.is_running());
+        assert_eq!(handler.borrow().calls.len(), 6);
+    }
+
+    #[test]
+    fn reset_with_transition_info_test() {
+        let sm = TestStatemachine5::new(StatemachineHandler::new());
+        let handler = sm.get_handler();
+        sm.event(TestStatemachine5Event::MySecondEvent);
+        assert_eq!(sm.get_state(), TestStatemachine5State::MySecondState);
+
+        // The entry and exit handlers take transition info, so they aren't called without an event
+        handler.borrow_mut().initial_on_exit_called = false;
+        handler.borrow_mut().second_on_entry_called = false;
+        sm.reset();
+        assert_eq!(sm.get_state(), TestStatemachine5State::MyInitialState);
+        assert!(sm.is_running());
+        assert!(Rc::ptr_eq(&handler, &sm.get_handler()));
+        assert!(!handler.borrow().initial_on_entry_called);
+        assert!(!handler.borrow().initial_on_exit_called);
+        assert!(!handler.borrow().second_on_entry_called);
+
+        sm.stop();
+        assert_eq!(sm.event(TestStatemachine5Event::MyFirstEvent), TestStatemachine5Outcome::Stopped);
+        sm.start();
+        sm.event(TestStatemachine5Event::MyFirstEvent);
+        assert!(handler.borrow().initial_on_entry_called);
+    }
+
+    #[test]
+    fn restore_lifecycle_test() {
+        let sm = TestStatemachine20::new(LifecycleHandler::default());
+        assert_eq!(sm.snapshot().lifecycle, TestStatemachine20Lifecycle::NotStarted);
+        sm.start();
+        sm.stop();
+        let snapshot = sm.snapshot();
+        assert_eq!(snapshot.lifecycle, TestStatemachine20Lifecycle::Stopped);
+
+        // A stopped statemachine stays stopped, no handler function is called
+        let restored = TestStatemachine20::restore(LifecycleHandler::default(), snapshot.clone());
+        assert!(!restored.is_running());
+        assert_eq!(restored.event(TestStatemachine20Event::MyFirstEvent), TestStatemachine20Outcome::Stopped);
+        assert_eq!(restored.snapshot(), snapshot);
+        assert!(restored.get_handler_ref().calls.is_empty());
+
+        let mut sm = TestStatemachine29::new(SerdeHandler { guard_value: true });
+        sm.start();
+        sm.stop();
+        let json = serde_json::to_string(&sm.snapshot()).unwrap();
+        assert!(json.contains(r#""lifecycle":"Stopped""#));
+        let restored = TestStatemachine29::restore(SerdeHandler { guard_value: true },
+                                                   serde_json::from_str(&json).unwrap());
+        assert!(!restored.is_running());
+    }
+
+    #[test]
+    fn event_before_start_test() {
+        let sm = TestStatemachine20::new(LifecycleHandler::default());
+        assert!(!sm.is_running());
+        sm.event(TestStatemachine20Event::MyFirstEvent);
+        assert!(sm.is_running());
+        assert_eq!(sm.get_handler_ref().calls, ["enter_initial_state", "exit_initial_state", "enter_second_state"]);
+        sm.start();
+        assert_eq!(sm.get_handler_ref().calls.len(), 3);
+    }
+
+    #[test]
+    fn legacy_start_test() {
+        let sm = TestStatemachine21::new(LifecycleHandler::default());
+        assert!(!sm.is_running());
+        sm.event(TestStatemachine21Event::MyFirstEvent);
+        assert!(sm.is_running());
+        assert_eq!(sm.get_handler_ref().calls, ["exit_initial_state", "enter_second_state"]);
+        sm.reset();
+        assert_eq!(sm.get_state(), TestStatemachine21State::MyInitialState);
+        assert_eq!(sm.get_handler_ref().calls, ["exit_initial_state", "enter_second_state"]);
+
+        let sm = TestStatemachine21::new(LifecycleHandler::default());
+        sm.start();
+        assert_eq!(sm.get_handler_ref().calls, ["enter_initial_state"]);
+    }
+
+
+    #[derive(Default)]
+    struct LifecycleRequestHandler {
+        calls: Vec<&'static str>,
+        post_on_entry: bool,
+        post_on_exit: bool,
+        sm: Weak<TestStatemachine30<Self>>,
+    }
+
+    impl TestStatemachine30Handler for LifecycleRequestHandler {
+        fn enter_initial_state(&mut self) {
+            self.calls.push("enter_initial_state");
+            if self.post_on_entry {
+                self.sm.upgrade().unwrap().event(TestStatemachine30Event::MyLoopEvent);
+            }
+        }
+        fn enter_second_state(&mut self) {
+            self.calls.push("enter_second_state");
+        }
+        fn exit_second_state(&mut self) {
+            self.calls.push("exit_second_state");
+            if self.post_on_exit {
+                self.sm.upgrade().unwrap().event(TestStatemachine30Event::MyStartEvent);
+            }
+        }
+        fn request_stop(&mut self) {
+            let sm = self.sm.upgrade().unwrap();
+            sm.stop();
+            assert_eq!(sm.event(TestStatemachine30Event::MyStartEvent), TestStatemachine30Outcome::Queued);
+        }
+        fn request_start(&mut self) {
+            self.sm.upgrade().unwrap().start();
+        }
+        fn request_reset(&mut self) {
+            self.sm.upgrade().unwrap().reset();
+        }
+    }
+
+    statemachine! {
+        Name                TestStatemachine30
+        InitialState        MyInitialState
+        MaxSteps            10
+
+        MyInitialState {
+            OnEntry enter_initial_state
+            MyStopEvent == request_stop => MySecondState
+            MyLoopEvent => MyInitialState
+        }
+        MySecondState {
+            OnEntry enter_second_state
+            OnExit exit_second_state
+            MyStartEvent == request_start => MySecondState
+            MyResetEvent == request_reset => MySecondState
+        }
+    }
+
+    #[test]
+    fn lifecycle_from_handler_test() {
+        let sm = Rc::new(TestStatemachine30::new(LifecycleRequestHandler::default()));
+        sm.get_handler_mut().sm = Rc::downgrade(&sm);
+        sm.start();
+
+        // The statemachine stops after the transition, the queued event is dropped
+        assert_eq!(sm.event(TestStatemachine30Event::MyStopEvent), TestStatemachine30Outcome::Transition {
+            old_state: TestStatemachine30State::MyInitialState,
+            new_state: TestStatemachine30State::MySecondState,
+        });
+        assert!(!sm.is_running());
+        assert_eq!(sm.get_handler_ref().calls, ["enter_initial_state", "enter_second_state", "exit_second_state"]);
+
+        // A stopped statemachine doesn't process the event, a running one ignores the start request
+        sm.get_handler_mut().calls.clear();
+        assert_eq!(sm.event(TestStatemachine30Event::MyStartEvent), TestStatemachine30Outcome::Stopped);
+        sm.start();
+        assert_eq!(sm.event(TestStatemachine30Event::MyStartEvent), TestStatemachine30Outcome::Transition {
+            old_state: TestStatemachine30State::MySecondState,
+            new_state: TestStatemachine30State::MySecondState,
+        });
+        assert!(sm.is_running());
+        assert_eq!(sm.get_handler_ref().calls, ["enter_second_state", "exit_second_state", "enter_second_state"]);
+
+        sm.get_handler_mut().calls.clear();
+        sm.event(TestStatemachine30Event::MyResetEvent);
+        assert_eq!(sm.get_state(), TestStatemachine30State::MyInitialState);
+        assert_eq!(sm.get_handler_ref().calls,
+                   ["exit_second_state", "enter_second_state", "exit_second_state", "enter_initial_state"]);
+
+        // Events posted on start count against MaxSteps, too
+        sm.get_handler_mut().calls.clear();
+        sm.get_handler_mut().post_on_entry = true;
+        sm.reset();
+        assert_eq!(sm.get_handler_ref().calls.iter().filter(|call| **call == "enter_initial_state").count(), 10);
+    }
+
+    #[test]
+    fn stop_drops_queued_events_test() {
+        let sm = Rc::new(TestStatemachine30::new(LifecycleRequestHandler::default()));
+        sm.get_handler_mut().sm = Rc::downgrade(&sm);
+        sm.event(TestStatemachine30Event::MyStopEvent);
+        sm.start();
+        assert_eq!(sm.get_state(), TestStatemachine30State::MySecondState);
+
+        // The event sent by the exit handler is dropped, and not processed after restarting
+        sm.get_handler_mut().calls.clear();
+        sm.get_handler_mut().post_on_exit = true;
+        sm.stop();
+        assert!(sm.snapshot().pending_events.is_empty());
+        sm.start();
+        assert_eq!(sm.get_handler_ref().calls, ["exit_second_state", "enter_second_state"]);
+    }
+
+
+    statemachine! {
+        [mock_handler, exit_handler_with_transition_info]
+        Name                TestStatemachine22
+        InitialState        MyInitialState
+        EventPayload        MyEventPayload
+        UnexpectedHandler   unexpected_handler
+
+        MyInitialState {
+            OnEntry enter_initial_state
+            OnExit exit_initial_state
+            MyFirstEvent[first_guard] ==first_action=> MySecondState
+        }
+        MySecondState {
+            MySecondEvent => MyInitialState
+        }
+    }
+
+    #[test]
+    fn mock_handler_test() {
+        let mut handler = TestStatemachine22MockHandler::new();
+        handler.set_first_guard(false);
+        let sm = TestStatemachine22::new(handler);
+        sm.event(TestStatemachine22Event::MyFirstEvent(MyEventPayload::new(1)));
+        sm.get_handler_mut().set_first_guard(true);
+        sm.event(TestStatemachine22Event::MyFirstEvent(MyEventPayload::new(2)));
+        sm.event(TestStatemachine22Event::MyFirstEvent(MyEventPayload::new(3)));
+
+        assert_eq!(sm.get_handler_ref().calls, [
+            TestStatemachine22MockCall::EnterInitialState,
+            TestStatemachine22MockCall::UnexpectedHandler {
+                state: TestStatemachine22State::MyInitialState,
+                event: TestStatemachine22EventKind::MyFirstEvent,
+            },
+            TestStatemachine22MockCall::ExitInitialState {
+                old_state: TestStatemachine22State::MyInitialState,
+                event: TestStatemachine22Event::MyFirstEvent(MyEventPayload::new(2)),
+                new_state: TestStatemachine22State::MySecondState,
+            },
+            TestStatemachine22MockCall::FirstAction { payload: MyEventPayload::new(2) },
+            TestStatemachine22MockCall::UnexpectedHandler {
+                state: TestStatemachine22State::MySecondState,
+                event: TestStatemachine22EventKind::MyFirstEvent,
+            },
+        ]);
+    }
+
+
+    statemachine! {
+        [mock_handler, random_walk]
+        Name                TestStatemachine23
+        InitialState        Idle
+        EventPayload        MyEventPayload
+
+        Idle {
+            Start ==start_action=> Running
+        }
+        Running {
+            Stop => Idle
+            Fail => Failed
+        }
+        Failed {}
+    }
+
+    statemachine! {
+        Name                TestStatemachine25
+        InitialState        Walk
+        UnexpectedHandler   unexpected_handler
+
+        Walk {
+            OnEntry switch_on_walk
+            TimerFired[button_pressed] => DontWalk
+        }
+        Scenario scenario_test {
+            TimerFired -> DontWalk;
+            ButtonPressed -> DontWalk;
+            TimerFired -> Walk
+        }
+        DontWalk {
+            TimerFired => Walk
+            ButtonPressed => DontWalk
+        }
+        Scenario scenario_unexpected_event_test {
+            ButtonPressed -> Walk;
+        }
+    }
+
+
+    statemachine! {
+        Name                TestStatemachine31
+        InitialState        Idle
+        EventPayload        MyEventPayload
+
+        Idle {
+            Start[motor_ready] ==start_action=> Running
+        }
+        Running {
+            Stop => Idle
+        }
+
+        Scenario payload_scenario_test {
+            Start(MyEventPayload::new(1)) -> Running;
+            Stop(MyEventPayload::new(2)) -> Idle;
+        }
+        Scenario guard_scenario_test {
+            Start(MyEventPayload::new(1)) [motor_ready=false] -> Idle;
+            Stop(MyEventPayload::new(2)) -> Idle;
+            Start(MyEventPayload::new(3)) [motor_ready=true] -> Running;
+        }
+    }
+
+    #[test]
+    fn random_walk_test() {
+        let payload = |_, walk: &mut TestStatemachine23RandomWalk| MyEventPayload::new(walk.next_below(10) as u32);
+
+        let sm = TestStatemachine23::new(TestStatemachine23MockHandler::new());
+        let mut walk = TestStatemachine23RandomWalk::new(7);
+        let error = sm.random_walk(&mut walk, 100, payload, |sm| {
+            if sm.get_state() == TestStatemachine23State::Failed {
+                Err("failed".to_string())
+            } else {
+                Ok(())
+            }
+        }).unwrap_err();
+        assert_eq!(error.seed, 7);
+        assert_eq!(error.message, "failed");
+        assert_eq!(error.events.last().unwrap().kind(), TestStatemachine23EventKind::Fail);
+        assert!(error.events.iter().all(|ev| match ev {
+            TestStatemachine23Event::Start(payload)
+            | TestStatemachine23Event::Stop(payload)
+            | TestStatemachine23Event::Fail(payload) => payload.value < 10,
+        }));
+
+        let replay = TestStatemachine23::new(TestStatemachine23MockHandler::new());
+        for ev in error.events.clone() {
+            replay.event(ev);
+        }
+        assert_eq!(replay.get_handler_ref().calls, sm.get_handler_ref().calls);
+
+        let sm = TestStatemachine23::new(TestStatemachine23MockHandler::new());
+        let mut walk = TestStatemachine23RandomWalk::new(7).respect_transitions(false);
+        assert!(sm.random_walk(&mut walk, 100, payload, |_| Ok(())).is_ok());
+    }
+
+
+    mod log_tests {
+        use simple_statemachine::statemachine;
+        use std::sync::Mutex;
+
+        struct CapturingLogger {
+            records: Mutex<Vec<String>>,
+        }
+
+        impl log::Log for CapturingLogger {
+            fn enabled(&self, _metadata: &log::Metadata) -> bool {
+                true
+            }
+            fn log(&self, record: &log::Record) {
+                self.records.lock().unwrap().push(record.args().to_string());
+            }
+            fn flush(&self) {}
+        }
+
+        static LOGGER: CapturingLogger = CapturingLogger { records: Mutex::new(Vec::new()) };
+
+        struct LoggedHandler {}
+
+        impl TestStatemachine18Handler for LoggedHandler {
+            fn reject_guard(&self) -> bool {
+                false
+            }
+            fn action_handler(&mut self) {}
+        }
+
+        statemachine! {
+            [log]
+            Name                TestStatemachine18
+            InitialState        MyInitialState
+
+            MyInitialState {
+                MyFirstEvent[reject_guard] => MySecondState
+                MySecondEvent == action_handler => MySecondState
+            }
+            MySecondState {}
+        }
+
+        #[test]
+        fn log_test() {
+            log::set_logger(&LOGGER).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+
+            let sm = TestStatemachine18::new(LoggedHandler {});
+            sm.event(TestStatemachine18Event::MyFirstEvent);
+            sm.event(TestStatemachine18Event::MySecondEvent);
+
+            let records: Vec<String> = LOGGER.records.lock().unwrap().iter()
+                .filter(|r| r.starts_with("TestStatemachine18:"))
+                .cloned()
+                .collect();
+            assert_eq!(records, [
+                "TestStatemachine18: dispatching event, state=MyInitialState, event=MyFirstEvent",
+                "TestStatemachine18: guard evaluated, state=MyInitialState, guard=reject_guard, accepted=false",
+                "TestStatemachine18: dispatching event, state=MyInitialState, event=MySecondEvent",
+                "TestStatemachine18: calling action, action=action_handler",
+                "TestStatemachine18: state changed, old_state=MyInitialState, new_state=MySecondState",
+            ]);
+        }
+    }
+
+
+    mod tracing_tests {
+        use simple_statemachine::statemachine;
+        use std::fmt::{Debug, Write};
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata};
+
+        struct FieldFormatter(String);
+
+        impl Visit for FieldFormatter {
+            fn record_str(&mut self, field: &Field, value: &str) {
+                write!(self.0, ", {}={}", field.name(), value).unwrap();
+            }
+            fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                write!(self.0, ", {}={:?}", field.name(), value).unwrap();
+            }
+        }
+
+        struct CapturingSubscriber {
+            records: Arc<Mutex<Vec<String>>>,
+            next_id: AtomicU64,
+        }
+
+        impl tracing::Subscriber for CapturingSubscriber {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut fields = FieldFormatter(format!("span {}", span.metadata().name()));
+                span.record(&mut fields);
+                self.records.lock().unwrap().push(fields.0);
+                Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed))
+            }
+            fn record(&self, _span: &Id, _values: &Record<'_>) {}
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+            fn event(&self, event: &Event<'_>) {
+                let mut fields = FieldFormatter(format!("{}", event.metadata().level()));
+                event.record(&mut fields);
+                self.records.lock().unwrap().push(fields.0);
+            }
+            fn enter(&self, _span: &Id) {
+                self.records.lock().unwrap().push("enter".to_string());
+            }
+            fn exit(&self, _span: &Id) {
+                self.records.lock().unwrap().push("exit".to_string());
+            }
+        }
+
+        struct TracedHandler {}
+
+        impl TestStatemachine28Handler for TracedHandler {
+            fn reject_guard(&self) -> bool {
+                false
+            }
+            fn action_handler(&mut self) {}
+        }
+
+        statemachine! {
+            [tracing]
+            Name                TestStatemachine28
+            InitialState        MyInitialState
+
+            MyInitialState {
+                MyFirstEvent[reject_guard] => MySecondState
+                MySecondEvent == action_handler => MySecondState
+            }
+            MySecondState {}
+        }
+
+        #[test]
+        fn tracing_test() {
+            let records = Arc::new(Mutex::new(Vec::new()));
+            let subscriber = CapturingSubscriber { records: records.clone(), next_id: AtomicU64::new(1) };
+            tracing::subscriber::with_default(subscriber, || {
+                let sm = TestStatemachine28::new(TracedHandler {});
+                sm.event(TestStatemachine28Event::MyFirstEvent);
+                sm.event(TestStatemachine28Event::MySecondEvent);
+            });
+
+            let records = records.lock().unwrap();
+            assert_eq!(*records, [
+                "span event, statemachine=TestStatemachine28, state=MyInitialState, event=MyFirstEvent",
+                "enter",
+                "DEBUG, message=dispatching event, statemachine=TestStatemachine28, state=MyInitialState, \
+                 event=MyFirstEvent",
+                "TRACE, message=guard evaluated, statemachine=TestStatemachine28, state=MyInitialState, \
+                 guard=reject_guard, accepted=false",
+                "exit",
+                "span event, statemachine=TestStatemachine28, state=MyInitialState, event=MySecondEvent",
+                "enter",
+                "DEBUG, message=dispatching event, statemachine=TestStatemachine28, state=MyInitialState, \
+                 event=MySecondEvent",
+                "TRACE, message=calling action, statemachine=TestStatemachine28, action=action_handler",
+                "DEBUG, message=state changed, statemachine=TestStatemachine28, old_state=MyInitialState, \
+                 new_state=MySecondState",
+                "exit",
+            ]);
+        }
+    }
+
+
+    mod coverage_tests {
+        use simple_statemachine::statemachine;
+
+        struct CoveredHandler {
+            guard_value: bool,
+        }
+
+        impl TestStatemachine24Handler for CoveredHandler {
+            fn accept_guard(&self) -> bool {
+                self.guard_value
+            }
+        }
+
+        statemachine! {
+            [coverage]
+            Name                TestStatemachine24
+            InitialState        MyInitialState
+
+            MyInitialState {
+                MyFirstEvent[accept_guard] => MySecondState
+                MySecondEvent => MySecondState
+            }
+            MySecondState {
+                MyFirstEvent => MyInitialState
+            }
+        }
+
+        #[test]
+        fn coverage_test() {
+            let sm = TestStatemachine24::new(CoveredHandler { guard_value: false });
+            sm.event(TestStatemachine24Event::MyFirstEvent);
+            sm.get_handler_mut().guard_value = true;
+            sm.event(TestStatemachine24Event::MyFirstEvent);
+            sm.event(TestStatemachine24Event::MyFirstEvent);
+
+            let transitions = sm.coverage().transitions();
+            assert_eq!((transitions[0].taken, transitions[0].rejected), (1, 1));
+            assert_eq!(transitions[0].to_string(),
+                       "MyInitialState --MyFirstEvent[accept_guard]--> MySecondState: taken 1 times, rejected 1 times");
+            assert_eq!(transitions[2].taken, 1);
+            let unexercised: Vec<_> = sm.coverage().unexercised().collect();
+            assert_eq!(unexercised, [transitions[1].clone()]);
+            assert_eq!(unexercised[0].event, TestStatemachine24EventKind::MySecondEvent);
+
+            // Each statemachine counts on its own, until merged
+            let other = TestStatemachine24::new(CoveredHandler { guard_value: true });
+            other.event(TestStatemachine24Event::MySecondEvent);
+            assert_eq!(other.coverage().unexercised().count(), 2);
+            let mut coverage = TestStatemachine24Coverage::new();
+            coverage.merge(&sm.coverage());
+            coverage.merge(&other.coverage());
+            assert_eq!(coverage.unexercised().count(), 0);
+            assert_eq!(coverage.transitions()[0].taken, 1);
+        }
+    }
 }
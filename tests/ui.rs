@@ -0,0 +1,7 @@
+// Checks the error messages of invalid statemachine definitions. Run with TRYBUILD=overwrite to update the expected
+// messages in tests/ui after changing them.
+#[test]
+fn ui_test() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,20 @@
+#![deny(deprecated)]
+
+use simple_statemachine::statemachine;
+
+statemachine!{
+    Name                WarnedMachine
+    InitialState        Locked
+
+    Locked {
+        Coin => Unlocked
+    }
+    Unlocked {
+        Push => Locked
+    }
+    Broken {
+        Repair => Locked
+    }
+}
+
+fn main() {}
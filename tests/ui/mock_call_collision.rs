@@ -0,0 +1,14 @@
+use simple_statemachine::statemachine;
+
+statemachine!{
+    [mock_handler]
+    Name                CollidingMachine
+    InitialState        Idle
+
+    Idle {
+        OnEntry enter_idle
+        Start == enter__idle => Idle
+    }
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use simple_statemachine::statemachine;
+
+statemachine!{
+    Name                ObserverMachine
+    InitialState        Idle
+
+    Idle {
+        Start == on_transition => Idle
+    }
+}
+
+fn main() {}
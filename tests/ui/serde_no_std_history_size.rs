@@ -0,0 +1,15 @@
+use simple_statemachine::statemachine;
+
+statemachine!{
+    [no_std, serde]
+    Name                SerdeMachine
+    InitialState        Idle
+    EventQueueCapacity  32
+    History             33
+
+    Idle {
+        Start => Idle
+    }
+}
+
+fn main() {}
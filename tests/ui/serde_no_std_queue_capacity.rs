@@ -0,0 +1,14 @@
+use simple_statemachine::statemachine;
+
+statemachine!{
+    [no_std, serde]
+    Name                SerdeMachine
+    InitialState        Idle
+    EventQueueCapacity  33
+
+    Idle {
+        Start => Idle
+    }
+}
+
+fn main() {}
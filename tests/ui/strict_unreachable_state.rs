@@ -0,0 +1,19 @@
+use simple_statemachine::statemachine;
+
+statemachine!{
+    [strict]
+    Name                StrictMachine
+    InitialState        Locked
+
+    Locked {
+        Coin => Unlocked
+    }
+    Unlocked {
+        Push => Locked
+    }
+    Broken {
+        Repair => Locked
+    }
+}
+
+fn main() {}